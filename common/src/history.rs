@@ -0,0 +1,154 @@
+//! The local record of every session and break, kept by the server whether or not the time made it
+//! into Jira.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum SegmentKind {
+  Work,
+  Break,
+  LongBreak,
+}
+
+/// A stretch of time during a session when the timer was paused, in milliseconds since the epoch
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Pause {
+  pub started: u64,
+  pub ended: u64,
+}
+
+/// A point part way through a work session where it moved on from one issue to another
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct IssueSwitch {
+  /// When the switch happened, in milliseconds since the unix epoch
+  pub at: u64,
+  /// The issue the session was tracking up to then
+  pub from: Option<String>,
+}
+
+/// The time a work session spent on one of the issues it tracked
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct IssueTime {
+  pub issue_key: Option<String>,
+  /// When work on the issue first started, in milliseconds since the unix epoch
+  pub started: u64,
+  /// Seconds spent on the issue, leaving out the time the session was paused
+  pub focused: u64,
+}
+
+/// Part of a work session put forward to Jira against one issue. A session usually has one, but
+/// time can be split off onto other issues.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SegmentWorklog {
+  pub issue_key: String,
+  /// When the work started, in milliseconds since the unix epoch
+  pub started: u64,
+  /// The seconds put forward once rounded, or held while `held` is set
+  pub logged: u64,
+  /// Whether it is too short to log and is held to be merged into the next one on the same issue
+  pub held: bool,
+  /// The id of the worklog Jira recorded it as, once it has been delivered
+  pub worklog_id: Option<String>,
+  /// The session whose worklog took in this held time, once one has
+  pub merged_into: Option<Uuid>,
+}
+
+/// A session or break, from when it started until it ended
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Segment {
+  pub id: Uuid,
+  pub kind: SegmentKind,
+  /// When the segment started, in milliseconds since the unix epoch
+  pub started: u64,
+  /// When the segment ended, or nothing while it is still going
+  pub ended: Option<u64>,
+  pub pauses: Vec<Pause>,
+  /// The issue a work session was tracking, since the last switch if there were any
+  pub issue_key: Option<String>,
+  /// Where a work session switched issues, in order
+  pub switches: Vec<IssueSwitch>,
+  /// Whether the segment ran its full length rather than being cut short
+  pub completed: bool,
+  /// What the session was put forward to Jira as
+  pub worklogs: Vec<SegmentWorklog>,
+}
+
+impl Segment {
+  pub fn new(kind: SegmentKind, started: u64, issue_key: Option<String>) -> Segment {
+    Segment {
+      id: Uuid::new_v4(),
+      kind,
+      started,
+      ended: None,
+      pauses: Vec::new(),
+      issue_key,
+      switches: Vec::new(),
+      completed: false,
+      worklogs: Vec::new(),
+    }
+  }
+
+  /// Seconds spent on the segment, leaving out the time it was paused, once it has ended
+  pub fn focused(&self) -> Option<u64> {
+    self.ended.map(|ended| self.focused_until(ended))
+  }
+
+  /// Seconds spent on the segment up to the time, leaving out the time it was paused
+  pub fn focused_until(&self, at: u64) -> u64 {
+    self.focused_between(self.started, at) / 1_000
+  }
+
+  /// Milliseconds between the two times that the segment wasn't paused
+  fn focused_between(&self, from: u64, to: u64) -> u64 {
+    let paused: u64 = self
+      .pauses
+      .iter()
+      .map(|pause| pause.ended.min(to).saturating_sub(pause.started.max(from)))
+      .sum();
+    to.saturating_sub(from).saturating_sub(paused)
+  }
+
+  /// The time spent on each issue up to the time, in the order they were first worked on. Issues
+  /// switched away from before any time was spent on them are left out.
+  pub fn issue_times(&self, until: u64) -> Vec<IssueTime> {
+    let mut spans = Vec::new();
+    let mut from = self.started;
+    for switch in &self.switches {
+      spans.push((&switch.from, from, switch.at));
+      from = switch.at;
+    }
+    spans.push((&self.issue_key, from, until));
+
+    // Added up in milliseconds, so short stretches on the same issue aren't each rounded down
+    let mut times: Vec<IssueTime> = Vec::new();
+    for (issue_key, from, to) in spans {
+      let focused = self.focused_between(from, to);
+      match times.iter_mut().find(|time| &time.issue_key == issue_key) {
+        Some(time) => time.focused += focused,
+        None => times.push(IssueTime {
+          issue_key: issue_key.clone(),
+          started: from,
+          focused,
+        }),
+      }
+    }
+    times.retain(|time| time.focused >= 1_000);
+    for time in &mut times {
+      time.focused /= 1_000;
+    }
+    times
+  }
+}
+
+/// Which segments to look up in the history
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HistoryQuery {
+  /// Only segments started at or after this time, in milliseconds since the unix epoch
+  pub from: Option<u64>,
+  /// Only segments started before this time, in milliseconds since the unix epoch
+  pub to: Option<u64>,
+  pub issue_key: Option<String>,
+  /// Only completed work sessions that have not been recorded in Jira
+  pub unlogged: bool,
+}
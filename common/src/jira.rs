@@ -0,0 +1,119 @@
+//! Jira types shared between the frontend and the server
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The Jira account the app is logged in as
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct JiraUser {
+  pub account_id: String,
+  pub display_name: String,
+  pub email_address: Option<String>,
+  /// A link to the largest avatar Jira has for the account
+  pub avatar_url: Option<String>,
+}
+
+/// The details needed to log in to a Jira Cloud site with an API token
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ApiTokenLogin {
+  /// The base url of the site, such as https://example.atlassian.net
+  pub site: String,
+  pub email: String,
+  pub token: String,
+}
+
+/// A finished stretch of work to record against an issue
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct WorkSegment {
+  pub issue_key: String,
+  /// When the work started, in milliseconds since the unix epoch
+  pub started: u64,
+  /// How long was spent on the work, in seconds
+  pub duration: u64,
+  pub comment: Option<String>,
+  /// The session in the local history the work was done in
+  #[serde(default)]
+  pub segment: Option<Uuid>,
+}
+
+/// A worklog Jira has recorded against an issue
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Worklog {
+  pub id: String,
+  pub issue_key: String,
+  /// The seconds Jira recorded as spent
+  pub time_spent_seconds: u64,
+}
+
+/// Enough of an issue to pick it out of a list
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct IssueSummary {
+  pub key: String,
+  pub summary: String,
+  /// The name of the issue's workflow status, such as "In Progress"
+  pub status: Option<String>,
+}
+
+/// The ways to search for issues to work on
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum IssueQuery {
+  /// Issues assigned to the current user in an open sprint that are not done yet
+  Mine,
+  /// Issues matching some text, or the issue with the key when the text looks like one
+  Text(String),
+  /// A search written in JQL
+  Jql(String),
+}
+
+impl IssueQuery {
+  /// The JQL that runs the search
+  pub fn jql(&self) -> String {
+    match self {
+      IssueQuery::Mine => "assignee = currentUser() AND sprint in openSprints() \
+        AND statusCategory != Done ORDER BY updated DESC"
+        .to_string(),
+      IssueQuery::Text(text) => {
+        let text = text.trim().replace('\\', "\\\\").replace('"', "\\\"");
+        match is_issue_key(&text) {
+          true => format!("key = \"{}\"", text.to_uppercase()),
+          false => format!("text ~ \"{}*\" ORDER BY updated DESC", text),
+        }
+      }
+      IssueQuery::Jql(jql) => jql.clone(),
+    }
+  }
+}
+
+/// The project an issue belongs to, which is the part of its key before the number
+pub fn project_key(issue_key: &str) -> &str {
+  issue_key
+    .rsplit_once('-')
+    .map(|(project, _)| project)
+    .unwrap_or(issue_key)
+}
+
+/// Whether the text is shaped like an issue key, such as JRD-12
+pub fn is_issue_key(text: &str) -> bool {
+  match text.split_once('-') {
+    Some((project, number)) => {
+      project.starts_with(|c: char| c.is_ascii_alphabetic())
+        && project
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+    }
+    None => false,
+  }
+}
+
+/// How many worklogs are waiting in the outbox to be delivered to Jira
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OutboxStatus {
+  /// Worklogs that will be sent, or sent again, once Jira can be reached
+  pub pending: usize,
+  /// Worklogs Jira refused, which are only sent again when retried
+  pub failed: usize,
+  /// Why the most recent attempt that did not make it failed
+  pub last_error: Option<String>,
+}
@@ -1,5 +1,17 @@
+pub mod history;
+pub mod jira;
 pub mod messages;
+pub mod report;
+pub mod rounding;
+pub mod settings;
+pub mod timer;
 
 pub mod prelude {
+  pub use crate::history::*;
+  pub use crate::jira::*;
   pub use crate::messages::*;
+  pub use crate::report::*;
+  pub use crate::rounding::*;
+  pub use crate::settings::*;
+  pub use crate::timer::*;
 }
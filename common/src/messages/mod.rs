@@ -1,10 +1,43 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::jira::{OutboxStatus, WorkSegment, Worklog};
+use crate::rounding::Outcome;
+use crate::timer::{TimerCommand, TimerSnapshot};
+
+/// The background processes the client is allowed to start on the server
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum ProcessKind {
+  /// Emits a tick every second until it is cancelled
+  Heartbeat,
+}
+
 /// A client request message to the server
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum RequestMessage {
   Heartbeat,
+  /// Add a new process to the end of the run line
+  Queue(ProcessKind),
+  /// Start a queued or paused process on the next available worker
+  Run(Uuid),
+  /// Pause a running process or pull it out of the queue to be run later
+  Pause(Uuid),
+  /// Gracefully stop a process, killing it if it does not exit in time
+  Cancel(Uuid),
+  /// Immediately stop a process
+  Kill(Uuid),
+  /// Retrieve the current status of a process
+  Status(Uuid),
+  /// Add the work segment to the outbox to be recorded in Jira as a worklog
+  RecordWork(WorkSegment),
+  /// Retrieve how many worklogs are waiting in the outbox
+  Outbox,
+  /// Send every worklog in the outbox again, including the ones Jira refused
+  RetryOutbox,
+  /// Retrieve the current state of the Pomodoro timer
+  Timer,
+  /// Change the Pomodoro timer
+  TimerCommand(TimerCommand),
 }
 
 /// A client request message to the server
@@ -13,11 +46,82 @@ pub struct Request {
   pub message: RequestMessage,
 }
 
+/// The state of a process managed by the server's LongRunner
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum ProcessStatus {
+  Queued,
+  Running,
+  /// Completed successfully with a serialized result
+  Finished(String),
+  Paused,
+  /// Ended before it had a chance to complete
+  Stopped,
+  /// Failed with the given error
+  Errored(String),
+}
+
 /// The synchronous message sent in response to a Request
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum Response {
   Ack(Uuid),
-  LongRunner(String),
+  /// The status of a process after a request was applied to it
+  Status(Uuid, ProcessStatus),
+  /// The request could not be completed
+  Error(String),
+  /// The process delivering the outbox, and the worklogs waiting in it
+  Outbox(Uuid, OutboxStatus),
+  /// The process sending out the Pomodoro timer, and the timer as it is now
+  Timer(Uuid, TimerSnapshot),
+  /// The process delivering the outbox, and why the rounding kept a session out of it
+  Rounded(Uuid, Outcome),
+  /// Something emitted by a running process
+  Payload(Payload),
+}
+
+impl Response {
+  /// The process the reply points the client to, whose emissions the sender will want to follow
+  pub fn process(&self) -> Option<Uuid> {
+    match self {
+      Response::Ack(guid)
+      | Response::Outbox(guid, _)
+      | Response::Timer(guid, _)
+      | Response::Rounded(guid, _) => Some(*guid),
+      _ => None,
+    }
+  }
+}
+
+/// The content of an emission from a running process. Both sides of the IPC boundary share this so
+/// that changing a payload is caught by the compiler on both.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Payload {
+  /// A beat of the heartbeat process
+  Heartbeat(HeartbeatTick),
+  /// A worklog was recorded in Jira
+  Worklog(Worklog),
+  /// The worklogs waiting in the outbox changed
+  Outbox(OutboxStatus),
+  /// The Pomodoro timer ticked or was changed
+  Timer(TimerSnapshot),
+  /// How much of its work a process has completed
+  Progress { done: u64, total: Option<u64> },
+  /// A line added to the process log
+  Log(String),
+  /// The process finished with the serialized result
+  Result(String),
+  /// The process failed with the given error
+  Error(String),
+  /// The process was stopped before it could complete
+  Stopped,
+}
+
+/// The payload emitted by the heartbeat process each time it beats
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct HeartbeatTick {
+  /// The number of beats since the process started, starting at 1
+  pub count: u64,
+  /// When the beat happened, in milliseconds since the unix epoch
+  pub timestamp: u64,
 }
 
 /// Messages that are sent out asynchronously without having been explicitly called. This returns a
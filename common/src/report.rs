@@ -0,0 +1,61 @@
+//! Productivity reports worked out from the local history
+
+use serde::{Deserialize, Serialize};
+
+/// The time focused over a day or a week
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeriodTotal {
+  /// The day as `2024-08-20`, or the ISO week as `2024-W34`
+  pub period: String,
+  /// Seconds spent working, not counting pauses
+  pub focused: u64,
+  /// Sessions that ran their full length
+  pub completed: u32,
+  /// Sessions cut short
+  pub abandoned: u32,
+}
+
+/// The time focused on an issue, or on every issue of a project
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct KeyTotal {
+  /// The issue key, the project key, or nothing for sessions without an issue
+  pub key: Option<String>,
+  /// Seconds spent working, not counting pauses
+  pub focused: u64,
+  pub sessions: u32,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+  /// The start of the range covered, in milliseconds since the unix epoch
+  pub from: u64,
+  /// The end of the range covered, in milliseconds since the unix epoch
+  pub to: u64,
+  /// Seconds spent working, not counting pauses
+  pub focused: u64,
+  pub completed: u32,
+  pub abandoned: u32,
+  /// Times sessions were paused
+  pub interruptions: u32,
+  /// Seconds sessions spent paused
+  pub paused: u64,
+  pub days: Vec<PeriodTotal>,
+  pub weeks: Vec<PeriodTotal>,
+  /// Most focused first
+  pub issues: Vec<KeyTotal>,
+  /// Most focused first
+  pub projects: Vec<KeyTotal>,
+  /// Completed sessions, each of which is owed a break
+  pub breaks_due: u32,
+  /// Completed sessions followed by a break
+  pub breaks_taken: u32,
+  /// Breaks that ran their full length
+  pub breaks_completed: u32,
+}
+
+impl Report {
+  /// The share of the breaks due that were taken, as a percentage
+  pub fn break_adherence(&self) -> Option<u32> {
+    (self.breaks_due > 0).then(|| self.breaks_taken * 100 / self.breaks_due)
+  }
+}
@@ -0,0 +1,130 @@
+//! The rules that turn the time a session ran for into the time logged to Jira. Each project can
+//! have its own rule, falling back on the default one.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::jira::project_key;
+
+/// How the time worked is rounded before it is logged
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum RoundingMode {
+  /// Log the time exactly as it was worked
+  #[default]
+  Off,
+  Nearest,
+  Up,
+}
+
+/// What happens to a session shorter than the minimum
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum ShortSessions {
+  /// Log it like any other
+  #[default]
+  Keep,
+  /// Leave it out of Jira
+  Discard,
+  /// Hold on to it until the next session on the same issue, and log them together
+  Merge,
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoundingRule {
+  pub mode: RoundingMode,
+  /// The minutes the time is rounded to a multiple of
+  pub minutes: u64,
+  /// Sessions shorter than this many minutes are dealt with as `short` says
+  pub minimum: u64,
+  pub short: ShortSessions,
+  /// The most minutes logged to the project in a day
+  pub daily_cap: Option<u64>,
+}
+
+impl Default for RoundingRule {
+  fn default() -> RoundingRule {
+    RoundingRule {
+      mode: RoundingMode::Off,
+      minutes: 15,
+      minimum: 0,
+      short: ShortSessions::Keep,
+      daily_cap: None,
+    }
+  }
+}
+
+/// What has already happened to the time on an issue, which the rule takes into account
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
+  /// Seconds already logged to the issue's project on the day the session started
+  pub logged_today: u64,
+  /// Seconds of short sessions on the issue held to be merged into the next one
+  pub held: u64,
+}
+
+/// What a rule makes of a finished session
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Outcome {
+  /// Log this many seconds, taking in any held sessions
+  Log(u64),
+  /// Hold the session's seconds until the next session on the issue
+  Hold(u64),
+  /// The session was too short to log
+  Short,
+  /// The project's time for the day has already reached the cap
+  OverCap,
+}
+
+impl RoundingRule {
+  /// The seconds rounded to the rule's multiple. Time worked is never rounded away to nothing.
+  pub fn round(&self, seconds: u64) -> u64 {
+    let step = self.minutes.max(1) * 60;
+    match self.mode {
+      _ if seconds == 0 => 0,
+      RoundingMode::Off => seconds,
+      RoundingMode::Nearest => ((seconds + step / 2) / step).max(1) * step,
+      RoundingMode::Up => seconds.div_ceil(step) * step,
+    }
+  }
+
+  /// What to log for a session that ran for the seconds, given what was logged before it
+  pub fn apply(&self, seconds: u64, ledger: &Ledger) -> Outcome {
+    let total = seconds + ledger.held;
+    if total < self.minimum * 60 {
+      match self.short {
+        ShortSessions::Keep => (),
+        ShortSessions::Discard => return Outcome::Short,
+        ShortSessions::Merge => return Outcome::Hold(seconds),
+      }
+    }
+    let rounded = self.round(total);
+    let allowed = match self.daily_cap {
+      Some(cap) => rounded.min((cap * 60).saturating_sub(ledger.logged_today)),
+      None => rounded,
+    };
+    match allowed {
+      0 if rounded > 0 => Outcome::OverCap,
+      0 => Outcome::Short,
+      allowed => Outcome::Log(allowed),
+    }
+  }
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoundingSettings {
+  /// The rule for projects without one of their own
+  pub default: RoundingRule,
+  /// The rules for particular projects, by project key
+  pub projects: BTreeMap<String, RoundingRule>,
+}
+
+impl RoundingSettings {
+  /// The rule for the project the issue belongs to
+  pub fn rule(&self, issue_key: &str) -> &RoundingRule {
+    self
+      .projects
+      .get(project_key(issue_key))
+      .unwrap_or(&self.default)
+  }
+}
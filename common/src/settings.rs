@@ -0,0 +1,74 @@
+//! The user's preferences, kept by the server between launches and changed from the settings
+//! panel. Every field has a default so settings saved by an older version still load.
+
+use serde::{Deserialize, Serialize};
+
+use crate::jira::IssueSummary;
+use crate::rounding::RoundingSettings;
+use crate::timer::TimerSettings;
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+  /// Whether to notify the desktop when a session or break runs out
+  pub enabled: bool,
+  /// How long a notification can put the next phase off for, in minutes
+  pub snooze_minutes: u64,
+}
+
+impl Default for NotificationSettings {
+  fn default() -> NotificationSettings {
+    NotificationSettings {
+      enabled: true,
+      snooze_minutes: 5,
+    }
+  }
+}
+
+/// What to log for a session that ran into overtime
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum OvertimeLog {
+  /// Only the length the session was planned to last
+  Planned,
+  /// All the time worked, overtime included
+  #[default]
+  Actual,
+  /// The planned length to the session's issue and the overtime to another
+  Split,
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OvertimeSettings {
+  /// Whether to ask what to log, rather than logging the default straight away
+  pub ask: bool,
+  pub default: OvertimeLog,
+  /// The issue overtime is split onto unless another is picked
+  pub split_issue: Option<String>,
+  /// Seconds of overtime that are logged along with the session without asking
+  pub grace: u64,
+}
+
+impl Default for OvertimeSettings {
+  fn default() -> OvertimeSettings {
+    OvertimeSettings {
+      ask: true,
+      default: OvertimeLog::Actual,
+      split_issue: None,
+      grace: 60,
+    }
+  }
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+  pub timer: TimerSettings,
+  /// The issue picked when the app starts
+  pub default_issue: Option<IssueSummary>,
+  /// The Jira Cloud site last logged in to, filled in on the login form
+  pub jira_site: Option<String>,
+  pub rounding: RoundingSettings,
+  pub notifications: NotificationSettings,
+  pub overtime: OvertimeSettings,
+}
@@ -0,0 +1,125 @@
+//! The Pomodoro timer as seen by the frontend. The server owns the timer and sends out a snapshot of
+//! it whenever it changes, while the frontend sends commands to change it.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::history::{IssueTime, Pause};
+
+#[derive(PartialEq, Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum TimerState {
+  #[default]
+  Paused,
+  Running,
+  Break,
+  /// The longer break taken once every few sessions
+  LongBreak,
+}
+
+/// How the Pomodoro cycle is laid out
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimerSettings {
+  /// Seconds a work session lasts
+  pub session_length: u64,
+  /// Seconds a short break lasts
+  pub short_break: u64,
+  /// Seconds a long break lasts
+  pub long_break: u64,
+  /// How many sessions are worked before a long break is due
+  pub long_break_every: u32,
+  /// Whether the next session or break starts on its own when the current one runs out
+  pub auto_start: bool,
+}
+
+impl Default for TimerSettings {
+  fn default() -> TimerSettings {
+    TimerSettings {
+      session_length: 25 * 60,
+      short_break: 5 * 60,
+      long_break: 15 * 60,
+      long_break_every: 4,
+      auto_start: false,
+    }
+  }
+}
+
+/// The ways the frontend can change the timer
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum TimerCommand {
+  /// Resume a paused session, or start a new one when on a break
+  Start,
+  /// Pause the session, keeping the time counted so far
+  Pause,
+  /// End the session and start counting down a break, a long one if it is due
+  Break,
+  /// End the session and start the next one straight away, skipping the break
+  Continue,
+  /// Go back to a fresh, paused session
+  Reset,
+  /// Change how long the current session or break is planned to last, in seconds
+  SetLength(u64),
+  /// Change how the cycle is laid out from the next session or break on
+  Configure(TimerSettings),
+  /// Change the issue the session is being worked against
+  SetIssue(Option<String>),
+}
+
+/// A work session that ran its full length, once it has ended
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CompletedSession {
+  /// The session's id in the local history
+  pub segment: Uuid,
+  /// When the session first started, in milliseconds since the unix epoch
+  pub started: u64,
+  /// When the session ended, which is later than planned when it ran into overtime
+  pub ended: u64,
+  /// Seconds the session was planned to last
+  pub length: u64,
+  /// Seconds actually worked, leaving out the pauses
+  pub focused: u64,
+  /// Where the session was interrupted
+  pub pauses: Vec<Pause>,
+  /// The time spent on each issue the session tracked
+  pub issues: Vec<IssueTime>,
+}
+
+impl CompletedSession {
+  /// Seconds worked beyond the planned length
+  pub fn overtime(&self) -> u64 {
+    self.focused.saturating_sub(self.length)
+  }
+}
+
+/// The timer at a point in time
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TimerSnapshot {
+  pub state: TimerState,
+  /// Seconds counted so far in the session or break
+  pub elapsed: u64,
+  /// Seconds the session or break is planned to last
+  pub length: u64,
+  /// When the session or break first started, in milliseconds since the unix epoch
+  pub started: Option<u64>,
+  /// Sessions completed since the last long break
+  pub cycle: u32,
+  /// Sessions completed since the app started
+  pub sessions: u32,
+  /// The most recent session to run its full length
+  pub completed: Option<CompletedSession>,
+  pub settings: TimerSettings,
+  /// The issue new sessions are worked against
+  pub issue: Option<String>,
+}
+
+impl TimerSnapshot {
+  /// Whether the session or break has run its full length
+  pub fn finished(&self) -> bool {
+    self.elapsed >= self.length
+  }
+
+  /// Seconds left until the session or break is finished
+  pub fn remaining(&self) -> u64 {
+    self.length.saturating_sub(self.elapsed)
+  }
+}
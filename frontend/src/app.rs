@@ -1,8 +1,5 @@
-use gloo_timers::callback::Timeout;
 use js_sys::Function;
-use serde::{Deserialize, Serialize};
-use serde_wasm_bindgen::to_value;
-use tracing::info;
+use tracing::{info, warn};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use yew::{function_component, html, prelude::*, ContextProvider, Html};
@@ -10,55 +7,25 @@ use yew::{function_component, html, prelude::*, ContextProvider, Html};
 use crate::prelude::*;
 use jiradoro_common::prelude::*;
 
-use crate::{
-  components::{heartbeat::Heartbeat, profile::*, timer_controls::*, timer_display::TimerDisplay},
-  helpers::*,
+use crate::components::{
+  heartbeat::Heartbeat, issue_picker::IssuePicker, pomodoro::Pomodoro, profile::*,
+  reports::Reports, settings::SettingsPanel,
 };
 
-#[derive(PartialEq, Copy, Clone, Debug)]
-pub enum TimerState {
-  Paused,
-  Running,
-  Break,
-}
-
-#[derive(Serialize, Deserialize)]
-struct SetTitleArgs<'a> {
-  title: &'a str,
-}
-
-pub fn get_tray_title(timer_state: TimerState, timer_duration: u32, session_length: u32) -> String {
-  match timer_state {
-    TimerState::Paused => String::from("Paused"),
-    TimerState::Running => {
-      if timer_duration >= session_length {
-        return format!("Finished session: {}", format_time(timer_duration));
-      }
-      return format!(
-        "In session: {}",
-        format_time(session_length - timer_duration)
-      );
-    }
-    TimerState::Break => {
-      if timer_duration >= session_length {
-        return format!("Finished break: {}", format_time(timer_duration));
-      }
-      return format!("Break: {}", format_time(session_length - timer_duration));
-    }
-  }
-}
-
 #[function_component(EmissionListener)]
 fn custard_listener() -> Html {
-  let on_emit = Callback::from(move |msg: Response| {
+  // Hand every emission to the LongRunner so it can be routed to the subscribed components
+  let long_runner = use_context::<LongRunnerCtx>().expect("LongRunner context not found");
+  let on_emit = Callback::from(move |msg: Emission| {
     info!("OnCustard received a message: {:#?}", msg);
+    long_runner.receive(msg);
   });
 
-  use_effect(move || {
+  use_effect_with((), move |_| {
     let on_emission = Closure::<dyn FnMut(JsValue)>::new(move |raw| {
       info!("Received on_custard message: {:#?}", raw);
       let msg: EmissionEvent = serde_wasm_bindgen::from_value(raw).unwrap();
-      on_emit.emit(msg.payload.message);
+      on_emit.emit(msg.payload);
     });
 
     let unlisten = crate::listen_("Emission", &on_emission);
@@ -84,61 +51,49 @@ fn custard_listener() -> Html {
 
 #[function_component(App)]
 pub fn app() -> Html {
-  let session_length = yew::prelude::use_state(|| 25 * 60); // Default 25 minutes
-  let timer_duration = yew::prelude::use_state(|| 0);
-  let timer_state = yew::prelude::use_state(|| TimerState::Paused);
+  let issue = yew::prelude::use_state(|| None::<IssueSummary>);
+  let settings = yew::prelude::use_state(|| None::<Settings>);
 
   let long_runner = LongRunnerCtx::new();
 
-  use_effect_with(
-    (
-      timer_duration.clone(),
-      timer_state.clone(),
-      session_length.clone(),
-    ),
-    move |props| {
-      let (timer_duration, timer_state, _) = props.clone();
-
-      let timeout = Timeout::new(1_000, move || {
-        if *timer_state != TimerState::Paused {
-          timer_duration.set(*timer_duration + 1);
+  // Load the saved settings. The issue is left to the picker, which follows the timer's.
+  {
+    let settings = settings.clone();
+    use_effect_with((), move |_| {
+      spawn_local(async move {
+        let reply = crate::invoke("get_settings", JsValue::from(js_sys::Object::new())).await;
+        match serde_wasm_bindgen::from_value::<Settings>(reply) {
+          Ok(loaded) => settings.set(Some(loaded)),
+          Err(err) => warn!("Could not read the settings: {}", err),
         }
       });
+    });
+  }
 
-      let (timer_duration, timer_state, session_length) = props.clone();
-
-      // Spawn a thread so that it can await the async call
-      spawn_local(async move {
-        let title = get_tray_title(*timer_state, *timer_duration, *session_length);
-
-        let args = to_value(&SetTitleArgs { title: &title[..] }).unwrap();
-        crate::invoke("set_title", args).await;
-      });
+  let site = settings
+    .as_ref()
+    .and_then(|settings| settings.jira_site.clone());
 
-      move || {
-        timeout.cancel();
-      }
-    },
-  );
+  let default_issue = settings
+    .as_ref()
+    .and_then(|settings| settings.default_issue.clone());
+  let rounding = settings.as_ref().map(|settings| settings.rounding.clone());
+  let overtime = settings.as_ref().map(|settings| settings.overtime.clone());
 
   html! {
     <div class={classes!("h-screen", "flex", "flex-col")}>
       <ContextProvider<LongRunnerCtx> context={long_runner}>
       <EmissionListener />
-      <div class={classes!("h-fit", "w-full")}>
-        <Profile button_status={Status::NotReady} />
+      <div class={classes!("h-fit", "w-full", "flex", "flex-row", "justify-between")}>
+        <div class={classes!("flex", "flex-row")}>
+          <SettingsPanel settings={settings.clone()} issue={(*issue).clone()} />
+          <Reports />
+        </div>
+        <Profile {site} />
       </div>
       <div class={classes!("flex", "items-center", "justify-center", "flex-col", "h-full")}>
-          <TimerDisplay
-            timer_state={timer_state.clone()}
-            timer_duration={timer_duration.clone()}
-            session_length={session_length.clone()}
-          />
-          <TimerControls
-            session_length={session_length.clone()}
-            timer_state={timer_state.clone()}
-            timer_duration={timer_duration.clone()}
-          />
+          <IssuePicker selected={issue.clone()} {default_issue} />
+          <Pomodoro issue={(*issue).clone()} {rounding} {overtime} />
       </div>
       <div class={classes!("h-16")}>
         <Heartbeat />
@@ -1,45 +1,89 @@
 use gloo_timers::callback::{Interval, Timeout};
 use serde_wasm_bindgen::to_value;
-use std::{
-  rc::Rc,
-  sync::{Arc, Mutex},
-};
-use tracing::info;
+use std::rc::Rc;
+use tracing::{info, warn};
 use uuid::Uuid;
 use yew::prelude::*;
 
 use crate::prelude::*;
 use jiradoro_common::prelude::*;
 
+/// How often the time since the last heartbeat is refreshed on the page, in milliseconds
+const REFRESH_INTERVAL: u32 = 100;
+
+/// The default number of seconds without a heartbeat before the backend is considered unresponsive
+pub const DEFAULT_WARN_AFTER: u32 = 5;
+
 pub enum Msg {
   Ack(Uuid),
   IncrementTimer,
   HeartbeatClick,
   EndHeartbeat,
-  /// A message received from the longrunner process. This is usually going to be a beat of the heart.
-  Emission(String),
+  /// No heartbeat has been received within the warning threshold
+  Unresponsive,
+  /// Restart the time since the last heartbeat as if one had just been received
+  ResetTimer,
+  /// A beat of the heart received from the longrunner process.
+  Emission(HeartbeatTick),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Default)]
 enum HeartbeatStatus {
+  #[default]
   Off,
   Started,
-  Running(Uuid),
+  Running,
   Cancelling,
 }
 
-impl Default for HeartbeatStatus {
-  fn default() -> HeartbeatStatus {
-    HeartbeatStatus::Off
-  }
-}
-
 #[derive(Debug, PartialEq)]
 struct HeartbeatState {
   guid: Uuid,
   long_runner: LongRunnerCtx,
   status: HeartbeatStatus,
   text: &'static str,
+  /// The server side heartbeat process, once the server has acknowledged it
+  process: Option<Uuid>,
+  /// The latest count received from the server heartbeat
+  count: u64,
+  /// When the latest beat was received, in milliseconds since the unix epoch
+  last_beat: Option<f64>,
+  /// The seconds since the latest beat, as of the last refresh
+  elapsed: f64,
+  /// Whether the warning threshold passed without a beat
+  unresponsive: bool,
+}
+
+impl HeartbeatState {
+  fn next(&self, status: HeartbeatStatus, text: &'static str) -> HeartbeatState {
+    HeartbeatState {
+      long_runner: self.long_runner.clone(),
+      guid: self.guid,
+      status,
+      text,
+      process: self.process,
+      count: self.count,
+      last_beat: self.last_beat,
+      elapsed: self.elapsed,
+      unresponsive: self.unresponsive,
+    }
+  }
+
+  /// Restart the time since the last beat from now
+  fn beat(&self, count: u64) -> HeartbeatState {
+    HeartbeatState {
+      count,
+      last_beat: Some(js_sys::Date::now()),
+      elapsed: 0.0,
+      unresponsive: false,
+      ..self.next(self.status.clone(), self.text)
+    }
+  }
+
+  fn send(&self, message: RequestMessage) {
+    let msg = to_value(&Request { message }).unwrap();
+    self.long_runner.send(&self.guid, msg);
+  }
 }
 
 impl Reducible for HeartbeatState {
@@ -52,70 +96,87 @@ impl Reducible for HeartbeatState {
       Msg::HeartbeatClick => match self.status {
         // Call to the RPC layer to start
         HeartbeatStatus::Off => {
-          let msg = to_value(&Request {
-            message: RequestMessage::Heartbeat,
-          })
-          .unwrap();
-          self.long_runner.send(&self.guid, msg);
-
-          HeartbeatState {
-            long_runner: self.long_runner.clone(),
-            guid: self.guid.clone(),
-            status: HeartbeatStatus::Started,
-            text: "Starting...",
+          self.send(RequestMessage::Queue(ProcessKind::Heartbeat));
+          self.next(HeartbeatStatus::Started, "Starting...")
+        }
+        HeartbeatStatus::Started => return self,
+        HeartbeatStatus::Running => {
+          if let Some(process) = self.process {
+            self.send(RequestMessage::Cancel(process));
           }
+          self.next(HeartbeatStatus::Cancelling, "Stopping ...")
+        }
+        HeartbeatStatus::Cancelling => return self.reduce(Msg::EndHeartbeat),
+      }
+      .into(),
+      Msg::Emission(tick) => {
+        // The first beat confirms the heartbeat is running on the server
+        let state = self.beat(tick.count);
+        match state.status {
+          HeartbeatStatus::Started => state.next(HeartbeatStatus::Running, "Cancel"),
+          _ => state,
+        }
+        .into()
+      }
+      Msg::Ack(process) => HeartbeatState {
+        process: Some(process),
+        ..match self.status {
+          HeartbeatStatus::Started => self.next(HeartbeatStatus::Running, "Cancel"),
+          _ => self.next(self.status.clone(), self.text),
         }
-        HeartbeatStatus::Started => HeartbeatState {
-          long_runner: self.long_runner.clone(),
-          guid: self.guid.clone(),
-          status: HeartbeatStatus::Running(Uuid::new_v4()),
-          text: "Cancel",
-        },
-        HeartbeatStatus::Running(_) => HeartbeatState {
-          long_runner: self.long_runner.clone(),
-          guid: self.guid.clone(),
-          status: HeartbeatStatus::Cancelling,
-          text: "Stopping ...",
-        },
-        HeartbeatStatus::Cancelling => HeartbeatState {
-          long_runner: self.long_runner.clone(),
-          guid: self.guid.clone(),
-          status: HeartbeatStatus::Off,
-          text: "Start",
-        },
       }
       .into(),
-      Msg::Emission(msg) => {
-        info!("Received an emission: {}", msg);
-        self.into()
+      Msg::EndHeartbeat => {
+        if let Some(process) = self.process {
+          self.long_runner.unsubscribe(process, self.guid);
+        }
+        HeartbeatState {
+          process: None,
+          ..self.next(HeartbeatStatus::Off, "Start")
+        }
+        .into()
       }
-      _ => {
-        info!("Got an unhandled type of message");
-        self.into()
+      Msg::IncrementTimer => match self.last_beat {
+        Some(last_beat) => HeartbeatState {
+          elapsed: (js_sys::Date::now() - last_beat) / 1000.0,
+          ..self.next(self.status.clone(), self.text)
+        }
+        .into(),
+        None => self,
+      },
+      Msg::Unresponsive => {
+        warn!("No heartbeat received in {:.2} seconds", self.elapsed);
+        HeartbeatState {
+          unresponsive: true,
+          ..self.next(self.status.clone(), self.text)
+        }
+        .into()
       }
+      Msg::ResetTimer => self.beat(self.count).into(),
     }
   }
 }
 
+/// The timers that keep the time since the last heartbeat current while the heartbeat is running
 #[derive(Debug, Default)]
 pub struct HeartbeatData {
-  pub guid: Uuid,
-  pub time_elapsed: f32,
-  pub count: i32,
-  pub server_guid: Option<Uuid>,
+  /// Refreshes the time since the last heartbeat
   pub interval: Option<Interval>,
+  /// Fires the warning if the next heartbeat does not arrive in time
   pub timeout: Option<Timeout>,
 }
 
-#[function_component]
-pub fn Heartbeat() -> Html {
-  // Start the timeout to fire a warning after the elapsed time.
-  // Start the interval for updating the page with the latest timeout.
-
-  let data = HeartbeatData::default();
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  /// Seconds without a heartbeat before the backend is flagged as unresponsive
+  #[prop_or(DEFAULT_WARN_AFTER)]
+  pub warn_after: u32,
+}
 
+#[function_component]
+pub fn Heartbeat(props: &Props) -> Html {
   // Makes a reference identifier for this component.
-  let guid = Uuid::new_v4();
+  let guid = *use_memo((), |_| Uuid::new_v4());
 
   // Get the long runner used to send messages
   let long_runner = use_context::<LongRunnerCtx>().expect("LongRunner context not found");
@@ -126,21 +187,100 @@ pub fn Heartbeat() -> Html {
     guid,
     status: HeartbeatStatus::default(),
     text: "Start",
+    process: None,
+    count: 0,
+    last_beat: None,
+    elapsed: 0.0,
+    unresponsive: false,
   });
 
+  // Start the interval for updating the page with the latest time since the last beat, and the
+  // timeout to fire a warning after the elapsed time. Both are restarted with every beat.
+  {
+    let state = heartbeat_state.clone();
+    let warn_after = props.warn_after;
+    let running = matches!(
+      heartbeat_state.status,
+      HeartbeatStatus::Running | HeartbeatStatus::Cancelling
+    );
+
+    use_effect_with(
+      (heartbeat_state.last_beat, running),
+      move |(last_beat, running)| {
+        let mut data = HeartbeatData::default();
+        if last_beat.is_some() && *running {
+          let refresh = state.clone();
+          data.interval = Some(Interval::new(REFRESH_INTERVAL, move || {
+            refresh.dispatch(Msg::IncrementTimer)
+          }));
+          data.timeout = Some(Timeout::new(warn_after * 1_000, move || {
+            state.dispatch(Msg::Unresponsive)
+          }));
+        }
+
+        move || drop(data)
+      },
+    );
+  }
+
   // A callback to feed to the longrunner. This is kept simple, as the reducer itself should
   // shoulder most of the burden for updating the component here.
-  let on_heartbeat: Callback<String> = {
+  let on_heartbeat: Callback<LongRunnerMsg> = {
     let state = heartbeat_state.clone();
 
     Callback::from(move |msg| {
       info!("LongRunner is sending back a message to the reducer");
-      state.dispatch(Msg::Emission(msg))
+      match msg {
+        LongRunnerMsg::Reply(Response::Ack(process)) => state.dispatch(Msg::Ack(process)),
+        LongRunnerMsg::Reply(Response::Status(_, status)) => match status {
+          ProcessStatus::Queued | ProcessStatus::Running | ProcessStatus::Paused => (),
+          _ => state.dispatch(Msg::EndHeartbeat),
+        },
+        LongRunnerMsg::Reply(Response::Error(err)) => {
+          warn!("The server could not handle the heartbeat request: {}", err);
+          state.dispatch(Msg::EndHeartbeat)
+        }
+        LongRunnerMsg::Reply(reply) => info!("Ignoring the reply {:?}", reply),
+        LongRunnerMsg::Emission(_, Payload::Heartbeat(tick)) => state.dispatch(Msg::Emission(tick)),
+        LongRunnerMsg::Emission(_, Payload::Error(err)) => {
+          warn!("The heartbeat failed: {}", err);
+          state.dispatch(Msg::EndHeartbeat)
+        }
+        LongRunnerMsg::Emission(_, Payload::Result(_) | Payload::Stopped) => {
+          state.dispatch(Msg::EndHeartbeat)
+        }
+        LongRunnerMsg::Emission(process, payload) => {
+          info!("Heartbeat {} sent {:?}", process, payload)
+        }
+      }
     })
   };
 
+  // Cancel a running heartbeat when the component goes away, as nothing would be left to stop it.
+  // The component may already be deregistered by then, which only means the reply isn't heard.
+  {
+    let running = use_mut_ref(|| None::<Uuid>);
+    *running.borrow_mut() = match heartbeat_state.status {
+      HeartbeatStatus::Running => heartbeat_state.process,
+      _ => None,
+    };
+
+    let state = heartbeat_state.clone();
+    use_effect_with((), move |_| {
+      move || {
+        if let Some(process) = running.take() {
+          info!(
+            "Cancelling heartbeat {} as the component is unmounted",
+            process
+          );
+          state.send(RequestMessage::Cancel(process));
+        }
+      }
+    });
+  }
+
   // Register the reducer for this component with the long_runner
-  let _ = heartbeat_state.long_runner.register(guid, on_heartbeat);
+  use_long_runner(guid, on_heartbeat);
 
   let on_click: Callback<()> = {
     let state = heartbeat_state.clone();
@@ -149,19 +289,33 @@ pub fn Heartbeat() -> Html {
     Callback::from(move |_| state.dispatch(Msg::HeartbeatClick))
   };
 
+  let on_reset: Callback<()> = {
+    let state = heartbeat_state.clone();
+    Callback::from(move |_| state.dispatch(Msg::ResetTimer))
+  };
+
   html! (
-      <div>
+      <div class={classes!(heartbeat_state.unresponsive.then_some("text-red-600"))}>
           <div>
-              {"Heart has a beat "}<b>{data.count}</b>{" times."}
+              {"Heart has a beat "}<b>{heartbeat_state.count}</b>{" times."}
           </div>
           <div>
-              {"It has been "}<b>{format!("{:.2}", data.time_elapsed)}</b>{" seconds since the last heartbeat."}
+              {"It has been "}<b>{format!("{:.2}", heartbeat_state.elapsed)}</b>{" seconds since the last heartbeat."}
           </div>
+          if heartbeat_state.unresponsive {
+              <div>
+                  <b>{"Backend unresponsive"}</b>
+              </div>
+          }
           <div>
               <button
                   class={classes!("cursor-pointer", "border-2", "text-gray", "p-2")}
                   onclick={move |_| {on_click.emit(())}}
               >{heartbeat_state.text}</button>
+              <button
+                  class={classes!("cursor-pointer", "border-2", "text-gray", "p-2")}
+                  onclick={move |_| {on_reset.emit(())}}
+              >{"Reset timer"}</button>
           </div>
       </div>
   )
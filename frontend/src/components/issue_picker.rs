@@ -0,0 +1,271 @@
+use gloo::storage::{LocalStorage, Storage};
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use tracing::{info, warn};
+use uuid::Uuid;
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+
+use crate::{helpers::bind, prelude::*};
+use jiradoro_common::prelude::*;
+
+/// Where the issues picked most recently are kept between runs
+const RECENT_KEY: &str = "jiradoro.issues.recent";
+
+/// Where the pinned issues are kept between runs
+const PINNED_KEY: &str = "jiradoro.issues.pinned";
+
+/// How many recently picked issues are remembered
+const RECENT_LIMIT: usize = 8;
+
+#[derive(Serialize)]
+struct SearchArgs<'a> {
+  query: &'a IssueQuery,
+}
+
+fn load(key: &str) -> Vec<IssueSummary> {
+  LocalStorage::get(key).unwrap_or_default()
+}
+
+fn save(key: &str, issues: &[IssueSummary]) {
+  if let Err(err) = LocalStorage::set(key, issues) {
+    warn!("Could not save {}: {}", key, err);
+  }
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  /// The issue the session is being worked against
+  pub selected: UseStateHandle<Option<IssueSummary>>,
+  /// The default issue from the settings, which the timer starts out tracking
+  #[prop_or_default]
+  pub default_issue: Option<IssueSummary>,
+}
+
+/// Picks the issue the timer is tracking, either by searching Jira or from the issues that were
+/// pinned or picked recently. The timer keeps the issue, so the picker starts out showing the one it
+/// already has, as after the window is reloaded, and only changes it when another is picked.
+#[function_component]
+pub fn IssuePicker(props: &Props) -> Html {
+  let guid = *use_memo((), |_| Uuid::new_v4());
+  let open = use_state(|| false);
+  let text = use_state(String::new);
+  let jql_mode = use_state(|| false);
+  let searching = use_state(|| false);
+  let error = use_state(|| None::<String>);
+  let results = use_state(Vec::<IssueSummary>::new);
+  let recent = use_state(|| load(RECENT_KEY));
+  let pinned = use_state(|| load(PINNED_KEY));
+
+  // Show the issue the timer is tracking whenever it changes on the server. The summary comes from
+  // whichever list the issue is in, as the timer only keeps the key.
+  let tracked = use_mut_ref(|| None::<Option<String>>);
+  let on_timer: Callback<LongRunnerMsg> = {
+    let (selected, default_issue) = (props.selected.clone(), props.default_issue.clone());
+    let known: Vec<IssueSummary> = pinned
+      .iter()
+      .chain(recent.iter())
+      .chain(results.iter())
+      .chain(default_issue.iter())
+      .cloned()
+      .collect();
+    Callback::from(move |msg| match msg {
+      LongRunnerMsg::Reply(Response::Timer(_, snapshot))
+      | LongRunnerMsg::Emission(_, Payload::Timer(snapshot)) => {
+        if tracked.borrow().as_ref() == Some(&snapshot.issue) {
+          return;
+        }
+        *tracked.borrow_mut() = Some(snapshot.issue.clone());
+        let issue = snapshot.issue.map(|key| {
+          known
+            .iter()
+            .find(|issue| issue.key == key)
+            .cloned()
+            .unwrap_or(IssueSummary {
+              key,
+              summary: String::new(),
+              status: None,
+            })
+        });
+        if issue.as_ref().map(|issue| &issue.key) != selected.as_ref().map(|issue| &issue.key) {
+          selected.set(issue);
+        }
+      }
+      msg => info!("Issue picker received {:?}", msg),
+    })
+  };
+  let long_runner = use_long_runner(guid, on_timer);
+
+  // Ask which issue the timer is tracking, which also follows it from here on
+  {
+    let long_runner = long_runner.clone();
+    use_effect_with((), move |_| {
+      let message = RequestMessage::Timer;
+      long_runner.send(&guid, to_value(&Request { message }).unwrap());
+    });
+  }
+
+  let search: Callback<IssueQuery> = {
+    let (searching, error, results) = (searching.clone(), error.clone(), results.clone());
+    Callback::from(move |query: IssueQuery| {
+      info!("Searching for issues with {:?}", query);
+      searching.set(true);
+      error.set(None);
+
+      let (searching, error, results) = (searching.clone(), error.clone(), results.clone());
+      spawn_local(async move {
+        let args = to_value(&SearchArgs { query: &query }).unwrap();
+        match crate::try_invoke("jira_search", args).await {
+          Ok(reply) => match from_value::<Vec<IssueSummary>>(reply) {
+            Ok(issues) => results.set(issues),
+            Err(err) => error.set(Some(format!("Could not read the issues: {}", err))),
+          },
+          Err(err) => {
+            let message = err.as_string().unwrap_or_else(|| format!("{:?}", err));
+            warn!("Could not search for issues: {}", message);
+            error.set(Some(message));
+          }
+        }
+        searching.set(false);
+      });
+    })
+  };
+
+  // Start off with the issues assigned to the user whenever the picker is opened
+  {
+    let search = search.clone();
+    use_effect_with(*open, move |open| {
+      if *open {
+        search.emit(IssueQuery::Mine);
+      }
+    });
+  }
+
+  let toggle: Callback<()> = {
+    let open = open.clone();
+    Callback::from(move |_| open.set(!*open))
+  };
+
+  let on_search: Callback<SubmitEvent> = {
+    let (text, jql_mode) = (text.clone(), jql_mode.clone());
+    Callback::from(move |event: SubmitEvent| {
+      event.prevent_default();
+      let query = match (text.trim(), *jql_mode) {
+        ("", _) => IssueQuery::Mine,
+        (jql, true) => IssueQuery::Jql(jql.to_string()),
+        (text, false) => IssueQuery::Text(text.to_string()),
+      };
+      search.emit(query);
+    })
+  };
+
+  let toggle_jql: Callback<()> = {
+    let jql_mode = jql_mode.clone();
+    Callback::from(move |_| jql_mode.set(!*jql_mode))
+  };
+
+  let select: Callback<IssueSummary> = {
+    let (selected, open, recent) = (props.selected.clone(), open.clone(), recent.clone());
+    let long_runner = long_runner.clone();
+    Callback::from(move |issue: IssueSummary| {
+      info!("Picked {}", issue.key);
+      let mut issues: Vec<IssueSummary> = recent
+        .iter()
+        .filter(|recent| recent.key != issue.key)
+        .cloned()
+        .collect();
+      issues.insert(0, issue.clone());
+      issues.truncate(RECENT_LIMIT);
+      save(RECENT_KEY, &issues);
+
+      recent.set(issues);
+      // Only a pick changes the timer's issue, so nothing else can add a switch to the session
+      let message = RequestMessage::TimerCommand(TimerCommand::SetIssue(Some(issue.key.clone())));
+      long_runner.send(&guid, to_value(&Request { message }).unwrap());
+      selected.set(Some(issue));
+      open.set(false);
+    })
+  };
+
+  let pin: Callback<IssueSummary> = {
+    let pinned = pinned.clone();
+    Callback::from(move |issue: IssueSummary| {
+      let mut issues = (*pinned).clone();
+      match issues.iter().position(|pinned| pinned.key == issue.key) {
+        Some(index) => {
+          issues.remove(index);
+        }
+        None => issues.push(issue),
+      }
+      save(PINNED_KEY, &issues);
+      pinned.set(issues);
+    })
+  };
+
+  let button = classes!("cursor-pointer", "border-2", "text-gray", "p-2");
+  let list = |title: &str, issues: &[IssueSummary]| -> Html {
+    if issues.is_empty() {
+      return html! {};
+    }
+    html! {
+      <div class={classes!("w-full")}>
+        <div class={classes!("font-bold")}>{title}</div>
+        { for issues.iter().map(|issue| {
+          let is_pinned = pinned.iter().any(|pinned| pinned.key == issue.key);
+          let (select, pin) = (select.clone(), pin.clone());
+          let (picked, pinning) = (issue.clone(), issue.clone());
+          html! {
+            <div class={classes!("flex", "flex-row", "items-center", "space-x-2")}>
+              <button class={classes!("cursor-pointer", "text-left", "grow")}
+                onclick={move |_| select.emit(picked.clone())}>
+                <b>{&issue.key}</b>{" "}{&issue.summary}
+                if let Some(status) = &issue.status {
+                  <span class={classes!("text-gray-500")}>{format!(" ({})", status)}</span>
+                }
+              </button>
+              <button class={classes!("cursor-pointer")} title={if is_pinned { "Unpin" } else { "Pin" }}
+                onclick={move |_| pin.emit(pinning.clone())}>
+                {if is_pinned { "★" } else { "☆" }}
+              </button>
+            </div>
+          }
+        }) }
+      </div>
+    }
+  };
+
+  html! {
+    <div class={classes!("flex", "flex-col", "items-center", "space-y-2", "pb-4", "w-96")}>
+      <div class={classes!("flex", "flex-row", "items-center", "space-x-2")}>
+        if let Some(issue) = &*props.selected {
+          <span><b>{&issue.key}</b>{" "}{&issue.summary}</span>
+        } else {
+          <span>{"No issue picked"}</span>
+        }
+        <button class={button.clone()} onclick={move |_| toggle.emit(())}>
+          {if *open { "Close" } else if props.selected.is_some() { "Change" } else { "Pick an issue" }}
+        </button>
+      </div>
+      if *open {
+        <form class={classes!("flex", "flex-row", "items-center", "space-x-2")} onsubmit={on_search}>
+          <input class={classes!("border-2", "p-1", "text-black")}
+            placeholder={if *jql_mode { "JQL" } else { "Search, or an issue key" }}
+            value={(*text).clone()} oninput={bind(&text)} />
+          <label>
+            <input type="checkbox" checked={*jql_mode} onclick={move |_| toggle_jql.emit(())} />
+            {" JQL"}
+          </label>
+          <button class={button} type="submit" disabled={*searching}>
+            {if *searching { "Searching..." } else { "Search" }}
+          </button>
+        </form>
+        if let Some(error) = &*error {
+          <div class={classes!("text-red-600")}>{error}</div>
+        }
+        {list("Pinned", &pinned)}
+        {list("Recent", &recent)}
+        {list("Results", &results)}
+      }
+    </div>
+  }
+}
@@ -2,5 +2,11 @@ pub mod timer_display;
 pub mod timer_controls;
 pub mod profile;
 pub mod heartbeat;
+pub mod issue_picker;
+pub mod worklog;
+pub mod split;
+pub mod pomodoro;
+pub mod settings;
+pub mod reports;
 
 pub mod prelude {}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use tracing::{info, warn};
+use uuid::Uuid;
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+
+use crate::{
+  components::{
+    timer_controls::TimerControls, timer_display::TimerDisplay, worklog::WorklogRecorder,
+  },
+  helpers::format_time,
+  prelude::*,
+};
+use jiradoro_common::prelude::*;
+
+#[derive(Serialize)]
+struct SetTitleArgs<'a> {
+  title: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgerArgs<'a> {
+  issue_key: &'a str,
+}
+
+pub fn get_tray_title(timer: &TimerSnapshot) -> String {
+  let (finished, active) = match timer.state {
+    TimerState::Paused => return String::from("Paused"),
+    TimerState::Running => ("Finished session", "In session"),
+    TimerState::Break => ("Finished break", "Break"),
+    TimerState::LongBreak => ("Finished long break", "Long break"),
+  };
+  if timer.finished() {
+    format!("{}: {}", finished, format_time(timer.elapsed))
+  } else {
+    format!("{}: {}", active, format_time(timer.remaining()))
+  }
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  /// The issue the session is being worked against
+  pub issue: Option<IssueSummary>,
+  /// The rounding rules, once the settings have been loaded
+  pub rounding: Option<RoundingSettings>,
+  /// What to do with overtime, once the settings have been loaded
+  pub overtime: Option<OvertimeSettings>,
+}
+
+/// Follows the timer kept by the server, handing its snapshots to the views and their commands
+/// back to the server. Nothing is shown until the server has said where the timer is up to.
+#[function_component]
+pub fn Pomodoro(props: &Props) -> Html {
+  let guid = *use_memo((), |_| Uuid::new_v4());
+  let timer = use_state(|| None::<TimerSnapshot>);
+
+  let on_reply: Callback<LongRunnerMsg> = {
+    let timer = timer.clone();
+    Callback::from(move |msg| match msg {
+      LongRunnerMsg::Reply(Response::Timer(_, snapshot))
+      | LongRunnerMsg::Emission(_, Payload::Timer(snapshot)) => timer.set(Some(snapshot)),
+      msg => info!("Pomodoro received {:?}", msg),
+    })
+  };
+  let long_runner = use_long_runner(guid, on_reply);
+
+  // Ask where the timer is up to, which also subscribes to it from here on
+  {
+    let long_runner = long_runner.clone();
+    use_effect_with((), move |_| {
+      let message = RequestMessage::Timer;
+      long_runner.send(&guid, to_value(&Request { message }).unwrap());
+    });
+  }
+
+  // Look up what the issue's project has logged today whenever another session is finished, for
+  // the rounding preview
+  let ledger = use_state(Ledger::default);
+  {
+    let ledger = ledger.clone();
+    let issue_key = props.issue.as_ref().map(|issue| issue.key.clone());
+    let sessions = timer.as_ref().map(|timer| timer.sessions);
+    use_effect_with((issue_key, sessions), move |(issue_key, _)| {
+      if let Some(issue_key) = issue_key.clone() {
+        spawn_local(async move {
+          let args = to_value(&LedgerArgs {
+            issue_key: &issue_key,
+          })
+          .unwrap();
+          match crate::try_invoke("worklog_ledger", args).await {
+            Ok(reply) => match from_value::<Ledger>(reply) {
+              Ok(loaded) => ledger.set(loaded),
+              Err(err) => warn!("Could not read the ledger: {}", err),
+            },
+            Err(err) => warn!("Could not look up the ledger: {:?}", err),
+          }
+        });
+      }
+    });
+  }
+
+  use_effect_with((*timer).clone(), move |timer| {
+    if let Some(timer) = timer {
+      let title = get_tray_title(timer);
+      // Spawn a thread so that it can await the async call
+      spawn_local(async move {
+        let args = to_value(&SetTitleArgs { title: &title[..] }).unwrap();
+        crate::invoke("set_title", args).await;
+      });
+    }
+  });
+
+  let on_command: Callback<TimerCommand> = Callback::from(move |command: TimerCommand| {
+    info!("Sending {:?} to the timer", command);
+    let message = RequestMessage::TimerCommand(command);
+    long_runner.send(&guid, to_value(&Request { message }).unwrap());
+  });
+
+  // What a session of the timer's length would be logged as once rounded
+  let preview = |timer: &TimerSnapshot| match (&props.issue, &props.rounding, timer.state) {
+    (Some(issue), Some(rounding), TimerState::Running | TimerState::Paused) => {
+      Some(rounding.rule(&issue.key).apply(timer.length, &ledger))
+    }
+    _ => None,
+  };
+
+  match &*timer {
+    Some(timer) => html! {
+      <>
+        <WorklogRecorder timer={timer.clone()} issue={props.issue.clone()}
+          overtime={props.overtime.clone().unwrap_or_default()} />
+        <TimerDisplay timer={timer.clone()} preview={preview(timer)}
+          on_command={on_command.clone()} />
+        <TimerControls timer={timer.clone()} {on_command} />
+      </>
+    },
+    None => html! { <div>{"Loading the timer..."}</div> },
+  }
+}
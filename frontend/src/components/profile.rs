@@ -1,50 +1,192 @@
-use serde::{Deserialize, Serialize};
-use serde_wasm_bindgen::to_value;
-use tracing::info;
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use tracing::{info, warn};
+use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
 
+use crate::helpers::bind;
 use jiradoro_common::prelude::*;
 
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
-pub enum Status {
-  NotReady,
-  Ready,
-  Running,
-  Finished,
+#[derive(Serialize)]
+struct LoginArgs<'a> {
+  login: &'a ApiTokenLogin,
+}
+
+/// The parts of the profile's state a login attempt updates once the server replies
+#[derive(Clone)]
+struct LoginHandles {
+  user: UseStateHandle<Option<JiraUser>>,
+  show_form: UseStateHandle<bool>,
+  pending: UseStateHandle<bool>,
+  error: UseStateHandle<Option<String>>,
+  token: UseStateHandle<String>,
+}
+
+impl LoginHandles {
+  /// Send a login command to the server, showing the user it logs in as or why it failed
+  fn start(&self, command: &'static str, args: JsValue) {
+    self.pending.set(true);
+    self.error.set(None);
+
+    let handles = self.clone();
+    spawn_local(async move {
+      match crate::try_invoke(command, args).await {
+        Ok(reply) => match from_value::<JiraUser>(reply) {
+          Ok(logged_in) => {
+            handles.user.set(Some(logged_in));
+            handles.show_form.set(false);
+            handles.token.set(String::new());
+          }
+          Err(err) => {
+            let message = format!("Could not read the Jira user: {}", err);
+            handles.error.set(Some(message))
+          }
+        },
+        Err(err) => {
+          let message = err.as_string().unwrap_or_else(|| format!("{:?}", err));
+          warn!("Could not log in to Jira: {}", message);
+          handles.error.set(Some(message));
+        }
+      }
+      handles.pending.set(false);
+    });
+  }
 }
 
 #[derive(Clone, Properties, PartialEq)]
 pub struct Props {
-  pub button_status: Status,
+  /// The site last logged in to, which the login form starts off with
+  pub site: Option<String>,
 }
 
+/// Shows the logged in Jira user, or the ways to log in when there isn't one: an API token, or
+/// Atlassian's own login for orgs that do not allow personal tokens.
 #[function_component]
-pub fn Profile(_props: &Props) -> Html {
-  let start_login: Callback<()> = {
+pub fn Profile(props: &Props) -> Html {
+  let user = use_state(|| None::<JiraUser>);
+  let show_form = use_state(|| false);
+  let pending = use_state(|| false);
+  let error = use_state(|| None::<String>);
+
+  let site = use_state(String::new);
+  let email = use_state(String::new);
+  let token = use_state(String::new);
+
+  // Fill in the remembered site, unless one has been typed in already
+  {
+    let site = site.clone();
+    use_effect_with(props.site.clone(), move |remembered| {
+      if let (Some(remembered), true) = (remembered, site.is_empty()) {
+        site.set(remembered.clone());
+      }
+    });
+  }
+
+  // Pick up a login the server already holds, such as after the page is reloaded
+  {
+    let user = user.clone();
+    use_effect_with((), move |_| {
+      spawn_local(async move {
+        let reply = crate::invoke("jira_user", JsValue::from(js_sys::Object::new())).await;
+        match from_value::<Option<JiraUser>>(reply) {
+          Ok(current) => user.set(current),
+          Err(err) => warn!("Could not read the current Jira user: {}", err),
+        }
+      });
+    });
+  }
+
+  let toggle_form: Callback<()> = {
+    let show_form = show_form.clone();
+    Callback::from(move |_| show_form.set(!*show_form))
+  };
+
+  let handles = LoginHandles {
+    user: user.clone(),
+    show_form: show_form.clone(),
+    pending: pending.clone(),
+    error: error.clone(),
+    token: token.clone(),
+  };
+
+  let login: Callback<SubmitEvent> = {
+    let handles = handles.clone();
+    let (site, email, token) = (site.clone(), email.clone(), token.clone());
+
+    Callback::from(move |event: SubmitEvent| {
+      event.prevent_default();
+      let login = ApiTokenLogin {
+        site: (*site).clone(),
+        email: (*email).clone(),
+        token: (*token).clone(),
+      };
+      info!("Logging in to {} as {}", login.site, login.email);
+      handles.start(
+        "jira_login",
+        to_value(&LoginArgs { login: &login }).unwrap(),
+      );
+    })
+  };
+
+  let oauth_login: Callback<()> = {
+    let handles = handles.clone();
     Callback::from(move |_| {
-      info!("Received a click from the profile");
+      info!("Logging in through Atlassian");
+      handles.start("jira_oauth_login", JsValue::from(js_sys::Object::new()));
+    })
+  };
+
+  let logout: Callback<()> = {
+    let user = user.clone();
+    Callback::from(move |_| {
+      let user = user.clone();
       spawn_local(async move {
-        info!("Spawned Local");
-        let args = to_value(&Request {
-          message: RequestMessage::Heartbeat,
-        })
-        .unwrap();
-        info!("About to 'call_server' with args: {:#?}", args);
-        let reply = crate::invoke("call_server", args).await;
-        info!("Received server reply {:#?}", reply);
-      })
+        crate::invoke("jira_logout", JsValue::from(js_sys::Object::new())).await;
+        user.set(None);
+      });
     })
   };
 
+  let button = classes!("cursor-pointer", "border-2", "text-gray", "p-2");
+  let input = classes!("border-2", "p-1", "text-black");
+
   html! (
-      <div class={classes!("text-right", "p-4", "cursor-pointer")}>
-        <button
-          class={classes!("cursor-pointer", "border-2", "text-gray", "p-2")}
-          onclick={move |_| {start_login.emit(())}}
-        >
-          {"profile button"}
-        </button>
+      <div class={classes!("text-right", "p-4")}>
+        if let Some(user) = (*user).clone() {
+          <div class={classes!("inline-flex", "items-center", "gap-2")}>
+            if let Some(avatar) = user.avatar_url {
+              <img class={classes!("h-8", "w-8", "rounded-full")} src={avatar} alt={user.display_name.clone()} />
+            }
+            <span>{user.display_name}</span>
+            <button class={button.clone()} onclick={move |_| {logout.emit(())}}>
+              {"Log out"}
+            </button>
+          </div>
+        } else {
+          <button class={button.clone()} onclick={move |_| {toggle_form.emit(())}}>
+            {"Log in to Jira"}
+          </button>
+        }
+        if *show_form && user.is_none() {
+          <form class={classes!("inline-flex", "flex-col", "items-end", "gap-2", "pt-2")} onsubmit={login}>
+            <input class={input.clone()} type="url" placeholder="https://example.atlassian.net"
+              value={(*site).clone()} oninput={bind(&site)} />
+            <input class={input.clone()} type="email" placeholder="Email"
+              value={(*email).clone()} oninput={bind(&email)} />
+            <input class={input} type="password" placeholder="API token"
+              value={(*token).clone()} oninput={bind(&token)} />
+            if let Some(error) = (*error).clone() {
+              <div class={classes!("text-red-600")}>{error}</div>
+            }
+            <button class={button.clone()} type="submit" disabled={*pending}>
+              {if *pending { "Logging in..." } else { "Log in" }}
+            </button>
+            <button class={button} type="button" disabled={*pending} onclick={move |_| {oauth_login.emit(())}}>
+              {"Log in with Atlassian"}
+            </button>
+          </form>
+        }
       </div>
   )
 }
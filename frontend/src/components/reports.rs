@@ -0,0 +1,284 @@
+use js_sys::Date;
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use tracing::warn;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+
+use crate::helpers::bind;
+use jiradoro_common::prelude::*;
+
+#[derive(Serialize)]
+struct ReportArgs {
+  from: u64,
+  to: u64,
+}
+
+#[derive(Serialize)]
+struct HistoryArgs<'a> {
+  query: &'a HistoryQuery,
+}
+
+/// Local midnight at the start of a day typed as `2024-08-20`
+fn start_of_day(day: &str) -> Option<Date> {
+  let date = Date::new(&JsValue::from_str(&format!("{}T00:00", day.trim())));
+  (!date.get_time().is_nan()).then_some(date)
+}
+
+/// Local midnight a number of days on from the date
+fn days_after(date: &Date, days: i32) -> Date {
+  Date::new_with_year_month_day(
+    date.get_full_year(),
+    date.get_month() as i32,
+    date.get_date() as i32 + days,
+  )
+}
+
+/// The date as a date field expects it
+fn day_field(date: &Date) -> String {
+  format!(
+    "{:04}-{:02}-{:02}",
+    date.get_full_year(),
+    date.get_month() + 1,
+    date.get_date()
+  )
+}
+
+/// The local time of day, in hours and minutes
+fn clock_time(millis: u64) -> String {
+  let date = Date::new(&JsValue::from_f64(millis as f64));
+  format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
+
+/// A length of time in hours and minutes
+fn hours(seconds: u64) -> String {
+  format!("{}h {:02}m", seconds / 3600, seconds / 60 % 60)
+}
+
+/// How long was spent on each day, week, issue and project over a range of days, read from the
+/// local history, along with how many sessions were finished and how many breaks were taken. Each
+/// session is listed with the times it was interrupted.
+#[function_component]
+pub fn Reports() -> Html {
+  let open = use_state(|| false);
+  let from = use_state(String::new);
+  let to = use_state(String::new);
+  let report = use_state(|| None::<Report>);
+  let sessions = use_state(Vec::<Segment>::new);
+  let error = use_state(|| None::<String>);
+
+  let load: Callback<(String, String)> = {
+    let (from, to, report, error) = (from.clone(), to.clone(), report.clone(), error.clone());
+    let sessions = sessions.clone();
+    Callback::from(move |(first, last): (String, String)| {
+      from.set(first.clone());
+      to.set(last.clone());
+      let range = start_of_day(&first).zip(start_of_day(&last));
+      let (first, last) = match range {
+        Some((first, last)) if first.get_time() <= last.get_time() => (first, last),
+        _ => return error.set(Some("Pick the first and last day to report on".to_string())),
+      };
+
+      let args = ReportArgs {
+        from: first.get_time() as u64,
+        to: days_after(&last, 1).get_time() as u64,
+      };
+      let query = HistoryQuery {
+        from: Some(args.from),
+        to: Some(args.to),
+        ..HistoryQuery::default()
+      };
+      let (report, sessions, error) = (report.clone(), sessions.clone(), error.clone());
+      spawn_local(async move {
+        let history = HistoryArgs { query: &query };
+        match crate::try_invoke("history_segments", to_value(&history).unwrap()).await {
+          Ok(reply) => match from_value::<Vec<Segment>>(reply) {
+            Ok(segments) => sessions.set(
+              segments
+                .into_iter()
+                .filter(|segment| segment.kind == SegmentKind::Work && segment.ended.is_some())
+                .collect(),
+            ),
+            Err(err) => warn!("Could not read the sessions: {}", err),
+          },
+          Err(err) => warn!("Could not load the sessions: {:?}", err),
+        }
+        match crate::try_invoke("history_report", to_value(&args).unwrap()).await {
+          Ok(reply) => match from_value::<Report>(reply) {
+            Ok(loaded) => {
+              report.set(Some(loaded));
+              error.set(None);
+            }
+            Err(err) => error.set(Some(format!("Could not read the report: {}", err))),
+          },
+          Err(err) => {
+            let message = err.as_string().unwrap_or_else(|| format!("{:?}", err));
+            warn!("Could not load the report: {}", message);
+            error.set(Some(message));
+          }
+        }
+      });
+    })
+  };
+
+  // Quick ranges ending today
+  let range = |label: &str, first: fn(&Date) -> Date| {
+    let load = load.clone();
+    html! {
+      <button class={classes!("cursor-pointer", "border-2", "text-gray", "p-1")}
+        onclick={move |_| {
+          let today = Date::new_0();
+          load.emit((day_field(&first(&today)), day_field(&today)))
+        }}>
+        {label}
+      </button>
+    }
+  };
+  let today = range("Today", |today| days_after(today, 0));
+  // Weeks start on a Monday
+  let this_week = range("This week", |today| {
+    days_after(today, -((today.get_day() as i32 + 6) % 7))
+  });
+  let last_seven = range("Last 7 days", |today| days_after(today, -6));
+
+  let toggle = {
+    let (open, load) = (open.clone(), load.clone());
+    move |_| {
+      if !*open {
+        let today = day_field(&Date::new_0());
+        load.emit((today.clone(), today));
+      }
+      open.set(!*open);
+    }
+  };
+  let on_submit = {
+    let (from, to, load) = (from.clone(), to.clone(), load.clone());
+    move |event: SubmitEvent| {
+      event.prevent_default();
+      load.emit(((*from).clone(), (*to).clone()));
+    }
+  };
+
+  let periods = |title: &str, totals: &[PeriodTotal]| {
+    html! {
+      <table class={classes!("w-full")}>
+        <tr class={classes!("text-left")}>
+          <th>{title}</th><th>{"Focused"}</th><th>{"Completed"}</th><th>{"Abandoned"}</th>
+        </tr>
+        { for totals.iter().map(|total| html! {
+          <tr>
+            <td>{&total.period}</td>
+            <td>{hours(total.focused)}</td>
+            <td>{total.completed}</td>
+            <td>{total.abandoned}</td>
+          </tr>
+        }) }
+      </table>
+    }
+  };
+  let keys = |title: &str, totals: &[KeyTotal]| {
+    html! {
+      <table class={classes!("w-full")}>
+        <tr class={classes!("text-left")}>
+          <th>{title}</th><th>{"Focused"}</th><th>{"Sessions"}</th>
+        </tr>
+        { for totals.iter().map(|total| html! {
+          <tr>
+            <td>{total.key.as_deref().unwrap_or("No issue")}</td>
+            <td>{hours(total.focused)}</td>
+            <td>{total.sessions}</td>
+          </tr>
+        }) }
+      </table>
+    }
+  };
+
+  // Each session with when it started and the pauses that interrupted it
+  let session_list = html! {
+    <table class={classes!("w-full")}>
+      <tr class={classes!("text-left")}>
+        <th>{"Session"}</th><th>{"Issue"}</th><th>{"Focused"}</th><th>{"Interrupted"}</th>
+      </tr>
+      { for sessions.iter().map(|session| {
+        let started = Date::new(&JsValue::from_f64(session.started as f64));
+        let pauses: Vec<String> = session
+          .pauses
+          .iter()
+          .map(|pause| format!("{}-{}", clock_time(pause.started), clock_time(pause.ended)))
+          .collect();
+        // A session that switched issues lists each of them
+        let issues: Vec<String> = session
+          .issue_times(session.ended.unwrap_or(session.started))
+          .into_iter()
+          .map(|time| time.issue_key.unwrap_or_else(|| "No issue".to_string()))
+          .collect();
+        html! {
+          <tr class={classes!((!session.completed).then_some("text-gray-500"))}>
+            <td>{format!("{} {}", day_field(&started), clock_time(session.started))}</td>
+            <td>{issues.join(", ")}</td>
+            <td>{hours(session.focused().unwrap_or_default())}</td>
+            <td>{pauses.join(", ")}</td>
+          </tr>
+        }
+      }) }
+    </table>
+  };
+
+  let input = classes!("border-2", "p-1", "text-black");
+  html! {
+    <div class={classes!("p-4")}>
+      <button class={classes!("cursor-pointer", "border-2", "text-gray", "p-2")} onclick={toggle}>
+        {if *open { "Close reports" } else { "Reports" }}
+      </button>
+      if *open {
+        <div class={classes!("flex", "flex-col", "space-y-2", "pt-2", "w-96")}>
+          <div class={classes!("flex", "flex-row", "space-x-2")}>
+            {today}
+            {this_week}
+            {last_seven}
+          </div>
+          <form class={classes!("flex", "flex-row", "space-x-2")} onsubmit={on_submit}>
+            <input class={input.clone()} type="date" value={(*from).clone()} oninput={bind(&from)} />
+            <input class={input} type="date" value={(*to).clone()} oninput={bind(&to)} />
+            <button class={classes!("cursor-pointer", "border-2", "text-gray", "p-1")} type="submit">
+              {"Show"}
+            </button>
+          </form>
+          if let Some(error) = &*error {
+            <div class={classes!("text-red-600")}>{error}</div>
+          }
+          if let Some(report) = &*report {
+            <div>
+              <b>{hours(report.focused)}</b>
+              {format!(" focused over {} finished and {} abandoned sessions",
+                report.completed, report.abandoned)}
+            </div>
+            <div>
+              {match report.break_adherence() {
+                Some(adherence) => format!(
+                  "Took {} of {} breaks due ({}%), {} of them in full",
+                  report.breaks_taken, report.breaks_due, adherence, report.breaks_completed
+                ),
+                None => "No breaks due yet".to_string(),
+              }}
+            </div>
+            {periods("Day", &report.days)}
+            if report.weeks.len() > 1 {
+              {periods("Week", &report.weeks)}
+            }
+            {keys("Issue", &report.issues)}
+            {keys("Project", &report.projects)}
+            if report.interruptions > 0 {
+              <div>
+                {format!("Interrupted {} times, for {} in all", report.interruptions,
+                  hours(report.paused))}
+              </div>
+            }
+            {session_list}
+          }
+        </div>
+      }
+    </div>
+  }
+}
@@ -0,0 +1,513 @@
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use tracing::{info, warn};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+
+use crate::helpers::bind;
+use jiradoro_common::prelude::*;
+
+#[derive(Serialize)]
+struct SaveArgs<'a> {
+  settings: &'a Settings,
+}
+
+/// The fields of the settings form, with the numbers kept as typed until the form is saved
+#[derive(Clone)]
+struct Fields {
+  session: UseStateHandle<String>,
+  short_break: UseStateHandle<String>,
+  long_break: UseStateHandle<String>,
+  every: UseStateHandle<String>,
+  auto_start: UseStateHandle<bool>,
+  default_issue: UseStateHandle<Option<IssueSummary>>,
+  site: UseStateHandle<String>,
+  /// Every rounding rule, as it was before the one being edited was changed
+  rules: UseStateHandle<RoundingSettings>,
+  /// The project whose rule is being edited, or nothing for the default rule
+  project: UseStateHandle<String>,
+  new_project: UseStateHandle<String>,
+  rounding: UseStateHandle<RoundingMode>,
+  rounding_minutes: UseStateHandle<String>,
+  minimum: UseStateHandle<String>,
+  short: UseStateHandle<ShortSessions>,
+  daily_cap: UseStateHandle<String>,
+  notify: UseStateHandle<bool>,
+  snooze: UseStateHandle<String>,
+  ask_overtime: UseStateHandle<bool>,
+  overtime: UseStateHandle<OvertimeLog>,
+  split_issue: UseStateHandle<String>,
+  grace: UseStateHandle<String>,
+}
+
+/// A whole number of minutes, greater than zero
+fn minutes(field: &str) -> Result<u64, String> {
+  match field.trim().parse::<u64>() {
+    Ok(minutes) if minutes > 0 => Ok(minutes),
+    _ => Err(format!("{:?} is not a number of minutes", field)),
+  }
+}
+
+/// A number of minutes, or nothing when left empty
+fn optional_minutes(field: &str) -> Result<Option<u64>, String> {
+  match field.trim() {
+    "" => Ok(None),
+    field => minutes(field).map(Some),
+  }
+}
+
+impl Fields {
+  /// Start the form off from the saved settings
+  fn fill(&self, settings: &Settings) {
+    let timer = &settings.timer;
+    self.session.set((timer.session_length / 60).to_string());
+    self.short_break.set((timer.short_break / 60).to_string());
+    self.long_break.set((timer.long_break / 60).to_string());
+    self.every.set(timer.long_break_every.to_string());
+    self.auto_start.set(timer.auto_start);
+    self.default_issue.set(settings.default_issue.clone());
+    self
+      .site
+      .set(settings.jira_site.clone().unwrap_or_default());
+    self.rules.set(settings.rounding.clone());
+    self.project.set(String::new());
+    self.fill_rule(&settings.rounding.default);
+    self.notify.set(settings.notifications.enabled);
+    self
+      .snooze
+      .set(settings.notifications.snooze_minutes.to_string());
+    let overtime = &settings.overtime;
+    self.ask_overtime.set(overtime.ask);
+    self.overtime.set(overtime.default);
+    self
+      .split_issue
+      .set(overtime.split_issue.clone().unwrap_or_default());
+    self.grace.set(match overtime.grace / 60 {
+      0 => String::new(),
+      grace => grace.to_string(),
+    });
+  }
+
+  /// Show a rounding rule in the rounding fields
+  fn fill_rule(&self, rule: &RoundingRule) {
+    self.rounding.set(rule.mode);
+    self.rounding_minutes.set(rule.minutes.to_string());
+    self.minimum.set(match rule.minimum {
+      0 => String::new(),
+      minimum => minimum.to_string(),
+    });
+    self.short.set(rule.short);
+    self.daily_cap.set(
+      rule
+        .daily_cap
+        .map(|cap| cap.to_string())
+        .unwrap_or_default(),
+    );
+  }
+
+  /// The rounding rule as filled in
+  fn read_rule(&self) -> Result<RoundingRule, String> {
+    Ok(RoundingRule {
+      mode: *self.rounding,
+      minutes: minutes(&self.rounding_minutes)?,
+      minimum: optional_minutes(&self.minimum)?.unwrap_or(0),
+      short: *self.short,
+      daily_cap: optional_minutes(&self.daily_cap)?,
+    })
+  }
+
+  /// Every rounding rule, with the one being edited as filled in
+  fn read_rounding(&self) -> Result<RoundingSettings, String> {
+    let mut rounding = (*self.rules).clone();
+    let rule = self.read_rule()?;
+    match self.project.is_empty() {
+      true => rounding.default = rule,
+      false => {
+        rounding.projects.insert((*self.project).clone(), rule);
+      }
+    }
+    Ok(rounding)
+  }
+
+  /// Move the rounding fields over to a project's rule, starting it off as a copy of the default
+  /// rule if it doesn't have one yet. An empty project edits the default rule.
+  fn edit_rule(&self, project: String) -> Result<(), String> {
+    let mut rounding = self.read_rounding()?;
+    let default = rounding.default.clone();
+    let rule = match project.is_empty() {
+      true => default,
+      false => rounding
+        .projects
+        .entry(project.clone())
+        .or_insert(default)
+        .clone(),
+    };
+    self.fill_rule(&rule);
+    self.rules.set(rounding);
+    self.project.set(project);
+    Ok(())
+  }
+
+  /// Drop the rule for the project being edited, so it goes back to the default rule
+  fn remove_rule(&self) {
+    let mut rounding = (*self.rules).clone();
+    rounding.projects.remove(&*self.project);
+    self.fill_rule(&rounding.default);
+    self.rules.set(rounding);
+    self.project.set(String::new());
+  }
+
+  /// The settings as filled in, or what is wrong with them
+  fn read(&self) -> Result<Settings, String> {
+    let site = self.site.trim();
+    let split_issue = self.split_issue.trim().to_uppercase();
+    Ok(Settings {
+      timer: TimerSettings {
+        session_length: minutes(&self.session)? * 60,
+        short_break: minutes(&self.short_break)? * 60,
+        long_break: minutes(&self.long_break)? * 60,
+        long_break_every: match self.every.trim().parse::<u32>() {
+          Ok(every) if every > 0 => every,
+          _ => return Err(format!("{:?} is not a number of sessions", *self.every)),
+        },
+        auto_start: *self.auto_start,
+      },
+      default_issue: (*self.default_issue).clone(),
+      jira_site: (!site.is_empty()).then(|| site.to_string()),
+      rounding: self.read_rounding()?,
+      notifications: NotificationSettings {
+        enabled: *self.notify,
+        snooze_minutes: minutes(&self.snooze)?,
+      },
+      overtime: OvertimeSettings {
+        ask: *self.ask_overtime,
+        default: *self.overtime,
+        split_issue: (!split_issue.is_empty()).then_some(split_issue),
+        grace: optional_minutes(&self.grace)?.unwrap_or(0) * 60,
+      },
+    })
+  }
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  /// The settings as last saved, once they have been loaded from the server
+  pub settings: UseStateHandle<Option<Settings>>,
+  /// The issue picked right now, which can be made the default
+  pub issue: Option<IssueSummary>,
+}
+
+/// Changes the settings the server keeps between launches: the Pomodoro cycle, the issue and Jira
+/// site to start with, how worklogs are rounded, which notifications are shown and what is logged
+/// for overtime.
+#[function_component]
+pub fn SettingsPanel(props: &Props) -> Html {
+  let open = use_state(|| false);
+  let saving = use_state(|| false);
+  let error = use_state(|| None::<String>);
+  let fields = Fields {
+    session: use_state(String::new),
+    short_break: use_state(String::new),
+    long_break: use_state(String::new),
+    every: use_state(String::new),
+    auto_start: use_state(|| false),
+    default_issue: use_state(|| None),
+    site: use_state(String::new),
+    rules: use_state(RoundingSettings::default),
+    project: use_state(String::new),
+    new_project: use_state(String::new),
+    rounding: use_state(RoundingMode::default),
+    rounding_minutes: use_state(String::new),
+    minimum: use_state(String::new),
+    short: use_state(ShortSessions::default),
+    daily_cap: use_state(String::new),
+    notify: use_state(|| false),
+    snooze: use_state(String::new),
+    ask_overtime: use_state(|| false),
+    overtime: use_state(OvertimeLog::default),
+    split_issue: use_state(String::new),
+    grace: use_state(String::new),
+  };
+
+  // Start the form off from the server's copy, which logging in may have changed since
+  let toggle: Callback<()> = {
+    let (open, error, fields) = (open.clone(), error.clone(), fields.clone());
+    let settings = props.settings.clone();
+    Callback::from(move |_| {
+      if *open {
+        return open.set(false);
+      }
+      let (open, error, fields, settings) = (
+        open.clone(),
+        error.clone(),
+        fields.clone(),
+        settings.clone(),
+      );
+      spawn_local(async move {
+        let reply = crate::invoke("get_settings", JsValue::from(js_sys::Object::new())).await;
+        match from_value::<Settings>(reply) {
+          Ok(current) => {
+            fields.fill(&current);
+            settings.set(Some(current));
+            error.set(None);
+            open.set(true);
+          }
+          Err(err) => warn!("Could not read the settings: {}", err),
+        }
+      });
+    })
+  };
+
+  let on_save: Callback<SubmitEvent> = {
+    let (open, saving, error, fields) =
+      (open.clone(), saving.clone(), error.clone(), fields.clone());
+    let settings = props.settings.clone();
+    Callback::from(move |event: SubmitEvent| {
+      event.prevent_default();
+      let changed = match fields.read() {
+        Ok(changed) => changed,
+        Err(err) => return error.set(Some(err)),
+      };
+      info!("Saving the settings");
+      saving.set(true);
+
+      let (open, saving, error, settings) = (
+        open.clone(),
+        saving.clone(),
+        error.clone(),
+        settings.clone(),
+      );
+      spawn_local(async move {
+        let args = to_value(&SaveArgs { settings: &changed }).unwrap();
+        match crate::try_invoke("save_settings", args).await {
+          Ok(reply) => match from_value::<Settings>(reply) {
+            Ok(saved) => {
+              settings.set(Some(saved));
+              open.set(false);
+            }
+            Err(err) => error.set(Some(format!("Could not read the settings: {}", err))),
+          },
+          Err(err) => {
+            let message = err.as_string().unwrap_or_else(|| format!("{:?}", err));
+            warn!("Could not save the settings: {}", message);
+            error.set(Some(message));
+          }
+        }
+        saving.set(false);
+      });
+    })
+  };
+
+  let input = classes!("border-2", "p-1", "text-black", "w-16");
+  let button = classes!("cursor-pointer", "border-2", "text-gray", "p-2");
+  let number = |label: &str, value: &UseStateHandle<String>| {
+    html! {
+      <label class={classes!("flex", "flex-row", "justify-between", "space-x-2")}>
+        <span>{label}</span>
+        <input class={input.clone()} type="number" min="1"
+          value={(**value).clone()} oninput={bind(value)} />
+      </label>
+    }
+  };
+  let checkbox = |label: &str, value: &UseStateHandle<bool>| {
+    let value = value.clone();
+    html! {
+      <label>
+        <input type="checkbox" checked={*value} onclick={move |_| value.set(!*value)} />
+        {format!(" {}", label)}
+      </label>
+    }
+  };
+  let rounding = |label: &str, mode: RoundingMode| {
+    let current = fields.rounding.clone();
+    html! {
+      <label>
+        <input type="radio" name="rounding" checked={*current == mode}
+          onclick={move |_| current.set(mode)} />
+        {format!(" {} ", label)}
+      </label>
+    }
+  };
+
+  let short = |label: &str, short: ShortSessions| {
+    let current = fields.short.clone();
+    html! {
+      <label>
+        <input type="radio" name="short" checked={*current == short}
+          onclick={move |_| current.set(short)} />
+        {format!(" {} ", label)}
+      </label>
+    }
+  };
+  let overtime = |label: &str, log: OvertimeLog| {
+    let current = fields.overtime.clone();
+    html! {
+      <label>
+        <input type="radio" name="overtime" checked={*current == log}
+          onclick={move |_| current.set(log)} />
+        {format!(" {} ", label)}
+      </label>
+    }
+  };
+  let optional = |label: &str, placeholder: &str, value: &UseStateHandle<String>| {
+    html! {
+      <label class={classes!("flex", "flex-row", "justify-between", "space-x-2")}>
+        <span>{label}</span>
+        <input class={input.clone()} type="number" min="1" placeholder={placeholder.to_string()}
+          value={(**value).clone()} oninput={bind(value)} />
+      </label>
+    }
+  };
+
+  // One button for the default rule and each project with its own, the one being edited in bold
+  let projects = {
+    let keys = std::iter::once(String::new()).chain(fields.rules.projects.keys().cloned());
+    let tabs = keys.map(|key| {
+      let (fields, error) = (fields.clone(), error.clone());
+      let mut classes = button.clone();
+      if key == *fields.project {
+        classes.push("font-bold");
+      }
+      let label = match key.is_empty() {
+        true => "Every project".to_string(),
+        false => key.clone(),
+      };
+      html! {
+        <button class={classes} type="button" onclick={move |_| {
+          if let Err(err) = fields.edit_rule(key.clone()) {
+            error.set(Some(err));
+          }
+        }}>
+          {label}
+        </button>
+      }
+    });
+    let add = {
+      let (fields, error) = (fields.clone(), error.clone());
+      move |_| {
+        let project = fields.new_project.trim().to_uppercase();
+        if project.is_empty() {
+          return;
+        }
+        match fields.edit_rule(project) {
+          Ok(()) => fields.new_project.set(String::new()),
+          Err(err) => error.set(Some(err)),
+        }
+      }
+    };
+    let remove = {
+      let fields = fields.clone();
+      move |_| fields.remove_rule()
+    };
+    html! {
+      <>
+        <div class={classes!("flex", "flex-row", "flex-wrap", "gap-1")}>
+          { for tabs }
+          if !fields.project.is_empty() {
+            <button class={button.clone()} type="button" onclick={remove}>{"Remove"}</button>
+          }
+        </div>
+        <div class={classes!("flex", "flex-row", "space-x-2")}>
+          <input class={classes!("border-2", "p-1", "text-black", "w-24")} placeholder="Project"
+            value={(*fields.new_project).clone()} oninput={bind(&fields.new_project)} />
+          <button class={button.clone()} type="button" onclick={add}>
+            {"Add a rule for the project"}
+          </button>
+        </div>
+      </>
+    }
+  };
+
+  let default_issue = {
+    let (current, picked) = (fields.default_issue.clone(), props.issue.clone());
+    let clear = current.clone();
+    html! {
+      <div class={classes!("flex", "flex-row", "items-center", "space-x-2")}>
+        <span>{"Start with "}</span>
+        if let Some(issue) = &*current {
+          <b>{&issue.key}</b>
+        } else {
+          <span>{"no issue picked"}</span>
+        }
+        if picked.is_some() && *current != picked {
+          <button class={button.clone()} type="button" onclick={move |_| current.set(picked.clone())}>
+            {"Use the picked issue"}
+          </button>
+        }
+        if clear.is_some() {
+          <button class={button.clone()} type="button" onclick={move |_| clear.set(None)}>
+            {"Clear"}
+          </button>
+        }
+      </div>
+    }
+  };
+
+  html! {
+    <div class={classes!("p-4")}>
+      <button class={button.clone()} onclick={move |_| toggle.emit(())}>
+        {if *open { "Close settings" } else { "Settings" }}
+      </button>
+      if *open {
+        <form class={classes!("flex", "flex-col", "space-y-1", "pt-2", "w-96")} onsubmit={on_save}>
+          <div class={classes!("font-bold")}>{"Timer"}</div>
+          {number("Session minutes", &fields.session)}
+          {number("Short break minutes", &fields.short_break)}
+          {number("Long break minutes", &fields.long_break)}
+          {number("Sessions before a long break", &fields.every)}
+          {checkbox("Start the next session or break automatically", &fields.auto_start)}
+
+          <div class={classes!("font-bold", "pt-2")}>{"Jira"}</div>
+          {default_issue}
+          <input class={classes!("border-2", "p-1", "text-black")} type="url"
+            placeholder="https://example.atlassian.net"
+            value={(*fields.site).clone()} oninput={bind(&fields.site)} />
+
+          <div class={classes!("font-bold", "pt-2")}>{"Worklog rounding"}</div>
+          {projects}
+          <div>
+            {rounding("Off", RoundingMode::Off)}
+            {rounding("Nearest", RoundingMode::Nearest)}
+            {rounding("Up", RoundingMode::Up)}
+          </div>
+          {number("Round to minutes", &fields.rounding_minutes)}
+          {optional("Minimum session minutes", "None", &fields.minimum)}
+          <div>
+            {"Shorter sessions: "}
+            {short("Keep", ShortSessions::Keep)}
+            {short("Discard", ShortSessions::Discard)}
+            {short("Merge", ShortSessions::Merge)}
+          </div>
+          {optional("Most minutes a day", "No cap", &fields.daily_cap)}
+
+          <div class={classes!("font-bold", "pt-2")}>{"Notifications"}</div>
+          {checkbox("Notify when a session or break runs out", &fields.notify)}
+          {number("Snooze minutes", &fields.snooze)}
+
+          <div class={classes!("font-bold", "pt-2")}>{"Overtime"}</div>
+          {checkbox("Ask what to log when a session runs over", &fields.ask_overtime)}
+          <div>
+            {"Log: "}
+            {overtime("Planned", OvertimeLog::Planned)}
+            {overtime("Actual", OvertimeLog::Actual)}
+            {overtime("Split", OvertimeLog::Split)}
+          </div>
+          <label class={classes!("flex", "flex-row", "justify-between", "space-x-2")}>
+            <span>{"Split overtime onto"}</span>
+            <input class={classes!("border-2", "p-1", "text-black", "w-24")} placeholder="Issue"
+              value={(*fields.split_issue).clone()} oninput={bind(&fields.split_issue)} />
+          </label>
+          {optional("Overtime minutes logged without asking", "None", &fields.grace)}
+
+          if let Some(error) = &*error {
+            <div class={classes!("text-red-600")}>{error}</div>
+          }
+          <button class={button} type="submit" disabled={*saving}>
+            {if *saving { "Saving..." } else { "Save" }}
+          </button>
+        </form>
+      }
+    </div>
+  }
+}
@@ -0,0 +1,156 @@
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+use jiradoro_common::prelude::*;
+
+/// One issue's share of the session as typed, with the minutes kept as text until it is logged
+#[derive(PartialEq, Clone, Debug)]
+struct Share {
+  issue_key: String,
+  /// When work on the issue started, or when the session did for an issue added by hand
+  started: u64,
+  minutes: String,
+}
+
+impl Share {
+  fn minutes(&self) -> Result<u64, String> {
+    match self.minutes.trim() {
+      "" => Ok(0),
+      minutes => minutes
+        .parse()
+        .map_err(|_| format!("{:?} is not a number of minutes", self.minutes)),
+    }
+  }
+}
+
+/// The worklogs for the shares as typed, or what is wrong with them. Shares of no minutes are left
+/// out, and each issue can only have one share as a session has one worklog per issue.
+fn read(session: &CompletedSession, shares: &[Share]) -> Result<Vec<WorkSegment>, String> {
+  let mut worklogs: Vec<WorkSegment> = Vec::new();
+  for share in shares {
+    let minutes = share.minutes()?;
+    if minutes == 0 {
+      continue;
+    }
+    let issue_key = share.issue_key.trim().to_uppercase();
+    if issue_key.is_empty() {
+      return Err(format!("Pick an issue for the {} minutes", minutes));
+    }
+    if worklogs
+      .iter()
+      .any(|worklog| worklog.issue_key == issue_key)
+    {
+      return Err(format!("{} can only be listed once", issue_key));
+    }
+    worklogs.push(WorkSegment {
+      issue_key,
+      started: share.started,
+      duration: minutes * 60,
+      comment: None,
+      segment: Some(session.segment),
+    });
+  }
+  match worklogs.is_empty() {
+    true => Err("There is nothing to log".to_string()),
+    false => Ok(worklogs),
+  }
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  /// A session that tracked more than one issue
+  pub session: CompletedSession,
+  /// Logs the worklogs as split
+  pub on_submit: Callback<Vec<WorkSegment>>,
+}
+
+/// Splits a finished session between the issues it was worked on before it is logged, one worklog
+/// for each. The time spent on each issue is filled in to start with, and can be changed, moved to
+/// another issue or shared with an issue that wasn't picked while the session ran.
+#[function_component]
+pub fn SplitDialog(props: &Props) -> Html {
+  let shares = use_state(|| {
+    props
+      .session
+      .issues
+      .iter()
+      .map(|time| Share {
+        issue_key: time.issue_key.clone().unwrap_or_default(),
+        started: time.started,
+        minutes: ((time.focused + 30) / 60).to_string(),
+      })
+      .collect::<Vec<Share>>()
+  });
+  let error = use_state(|| None::<String>);
+
+  // Change one of the shares as it is typed
+  let edit = |index: usize, change: fn(&mut Share, String)| {
+    let shares = shares.clone();
+    Callback::from(move |event: InputEvent| {
+      let input: HtmlInputElement = event.target_unchecked_into();
+      let mut changed = (*shares).clone();
+      change(&mut changed[index], input.value());
+      shares.set(changed);
+    })
+  };
+  let remove = |index: usize| {
+    let shares = shares.clone();
+    Callback::from(move |_| {
+      let mut changed = (*shares).clone();
+      changed.remove(index);
+      shares.set(changed);
+    })
+  };
+  let add = {
+    let (shares, started) = (shares.clone(), props.session.started);
+    move |_| {
+      let mut changed = (*shares).clone();
+      changed.push(Share {
+        issue_key: String::new(),
+        started,
+        minutes: String::new(),
+      });
+      shares.set(changed);
+    }
+  };
+  let submit = {
+    let (shares, error) = (shares.clone(), error.clone());
+    let (session, on_submit) = (props.session.clone(), props.on_submit.clone());
+    move |_| match read(&session, &shares) {
+      Ok(worklogs) => on_submit.emit(worklogs),
+      Err(err) => error.set(Some(err)),
+    }
+  };
+
+  let total: u64 = shares.iter().filter_map(|share| share.minutes().ok()).sum();
+  let input = classes!("border-2", "p-1", "text-black");
+  let button = classes!("cursor-pointer", "border-2", "text-gray", "px-2");
+  html! {
+    <div class={classes!("flex", "flex-col", "items-center", "space-y-2")}>
+      <div>
+        {format!("The session went to more than one issue. Worked {} of {} planned minutes,",
+          props.session.focused / 60, props.session.length / 60)}
+        {format!(" {} split so far.", total)}
+      </div>
+      { for shares.iter().enumerate().map(|(index, share)| html! {
+        <div class={classes!("flex", "flex-row", "items-center", "space-x-2")}>
+          <input class={classes!(input.clone(), "w-24")} placeholder="Issue"
+            value={share.issue_key.clone()}
+            oninput={edit(index, |share, value| share.issue_key = value)} />
+          <input class={classes!(input.clone(), "w-16")} type="number" min="0"
+            value={share.minutes.clone()}
+            oninput={edit(index, |share, value| share.minutes = value)} />
+          <span>{"min"}</span>
+          <button class={button.clone()} onclick={remove(index)}>{"Remove"}</button>
+        </div>
+      }) }
+      if let Some(error) = &*error {
+        <div class={classes!("text-red-600")}>{error}</div>
+      }
+      <div class={classes!("flex", "flex-row", "space-x-2")}>
+        <button class={button.clone()} onclick={add}>{"Add an issue"}</button>
+        <button class={button} onclick={submit}>{"Log"}</button>
+      </div>
+    </div>
+  }
+}
@@ -1,69 +1,84 @@
-use crate::{app::TimerState, helpers::format_time};
+use crate::helpers::format_time;
+use jiradoro_common::prelude::*;
 use yew::prelude::*;
 
+/// The shortest a session or break can be made with the buttons
+const MIN_LENGTH: u64 = 5 * 60;
+
 // Define the component's properties
 #[derive(Clone, PartialEq, Properties)]
 pub struct Props {
-  pub timer_state: UseStateHandle<TimerState>,
-  pub timer_duration: UseStateHandle<u32>,
-  pub session_length: UseStateHandle<u32>,
+  pub timer: TimerSnapshot,
+  /// What the session would be logged to Jira as once rounded, when there is an issue to log to
+  pub preview: Option<Outcome>,
+  /// Sends a command to the timer on the server
+  pub on_command: Callback<TimerCommand>,
 }
 
 #[function_component]
 pub fn TimerDisplay(props: &Props) -> Html {
-  let is_expired = *props.timer_duration > *props.session_length;
+  let timer = &props.timer;
+  let is_expired = timer.finished();
 
   let increase_session_length = {
-    let session_length = props.session_length.clone();
-
+    let (on_command, length) = (props.on_command.clone(), timer.length);
     Callback::from(move |_: ()| {
-      session_length.set(*session_length + 60 * 5); // Increase by 5 minutes
+      on_command.emit(TimerCommand::SetLength(length + 60 * 5)); // Increase by 5 minutes
     })
   };
 
   let decrease_session_length = {
-    let session_length = props.session_length.clone();
+    let (on_command, length) = (props.on_command.clone(), timer.length);
     Callback::from(move |_: ()| {
-      session_length.set(*session_length - 60 * 5); // Decrease by 5 minutes
+      let length = length.saturating_sub(60 * 5).max(MIN_LENGTH); // Decrease by 5 minutes
+      on_command.emit(TimerCommand::SetLength(length));
     })
   };
 
-  let session_state_display = {
-    let is_expired = *props.timer_duration > *props.session_length;
-
-    let timer_state = props.timer_state.clone();
-    match *timer_state {
-      TimerState::Paused => "Paused".to_string(),
-      TimerState::Break => {
-        if is_expired {
-          "Finished break".to_string()
-        } else {
-          "On break".to_string()
-        }
+  let session_state_display = match timer.state {
+    TimerState::Paused => "Paused".to_string(),
+    TimerState::Break => {
+      if is_expired {
+        "Finished break".to_string()
+      } else {
+        "On break".to_string()
       }
-      TimerState::Running => {
-        if is_expired {
-          "Finished session".to_string()
-        } else {
-          "In session".to_string()
-        }
+    }
+    TimerState::LongBreak => {
+      if is_expired {
+        "Finished long break".to_string()
+      } else {
+        "On a long break".to_string()
+      }
+    }
+    TimerState::Running => {
+      if is_expired {
+        "Finished session".to_string()
+      } else {
+        "In session".to_string()
       }
     }
   };
 
-  let get_session_display: String = {
-    let Props {
-      timer_duration,
-      session_length,
-      ..
-    } = props.clone();
-    if is_expired {
-      format_time(*timer_duration)
-    } else {
-      format_time(*session_length - *timer_duration)
-    }
+  let get_session_display: String = if is_expired {
+    format_time(timer.elapsed)
+  } else {
+    format_time(timer.remaining())
   };
 
+  // One mark for each session in the cycle, filled in as they are completed
+  let every = timer.settings.long_break_every.max(1);
+  let cycle_display: String = (0..every)
+    .map(|session| if session < timer.cycle { '●' } else { '○' })
+    .collect();
+
+  let preview_display = props.preview.map(|preview| match preview {
+    Outcome::Log(seconds) => format!("Logs as {}m", seconds / 60),
+    Outcome::Hold(_) => "Held to log with the next session".to_string(),
+    Outcome::Short => "Too short to log".to_string(),
+    Outcome::OverCap => "Over today's cap for the project".to_string(),
+  });
+
   html! {
     <div class={classes!("flex", "flex-col", "space-y-2", "items-center")}>
         <div class={classes!("flex", "flex-row", "space-x-3")}>
@@ -82,6 +97,12 @@ pub fn TimerDisplay(props: &Props) -> Html {
             </button>
         </div>
         {session_state_display}
+        <div title={format!("{} sessions completed", timer.sessions)}>
+            {cycle_display}
+        </div>
+        if let Some(preview) = preview_display {
+            <div class={classes!("text-sm")}>{preview}</div>
+        }
     </div>
   }
 }
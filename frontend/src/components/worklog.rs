@@ -0,0 +1,401 @@
+use serde::Serialize;
+use serde_wasm_bindgen::{from_value, to_value};
+use std::collections::HashSet;
+use std::rc::Rc;
+use tracing::{info, warn};
+use uuid::Uuid;
+use wasm_bindgen_futures::spawn_local;
+use yew::prelude::*;
+
+use crate::{components::split::SplitDialog, helpers::bind, prelude::*};
+use jiradoro_common::prelude::*;
+
+/// How far back to look for sessions that were never recorded when the window loads, in
+/// milliseconds. Older ones are left to be reconciled from the reports.
+const UNRECORDED_WITHIN: u64 = 24 * 60 * 60 * 1_000;
+
+/// Where recording the latest session in Jira is up to
+#[derive(Debug, PartialEq, Clone, Default)]
+enum Recording {
+  #[default]
+  Idle,
+  /// Waiting in the outbox for Jira to be reached
+  Sending,
+  Recorded(Worklog),
+  /// Waiting to be told what to log for a session that ran into overtime
+  Overtime(CompletedSession, String),
+  /// Waiting for a session that tracked more than one issue to be split between them
+  Splitting(CompletedSession),
+  /// Waiting for an issue to be picked for a session that finished without one
+  NeedsIssue(CompletedSession),
+  /// Kept out of Jira by the rounding rule for the issue's project
+  Rounded(Outcome),
+  Failed(String),
+}
+
+/// The finished sessions waiting to be offered for logging, oldest first
+#[derive(PartialEq, Default)]
+struct Unrecorded {
+  waiting: Vec<CompletedSession>,
+  /// Every session that has waited in this window, so none is offered twice
+  seen: HashSet<Uuid>,
+}
+
+enum Waiting {
+  Add(Vec<CompletedSession>),
+  /// Take the session out of the line to be offered
+  Take(Uuid),
+}
+
+impl Reducible for Unrecorded {
+  type Action = Waiting;
+
+  fn reduce(self: Rc<Self>, action: Waiting) -> Rc<Self> {
+    let (mut waiting, mut seen) = (self.waiting.clone(), self.seen.clone());
+    match action {
+      Waiting::Add(sessions) => {
+        for session in sessions {
+          if seen.insert(session.segment) {
+            waiting.push(session);
+          }
+        }
+        waiting.sort_by_key(|session| session.started);
+      }
+      Waiting::Take(segment) => waiting.retain(|session| session.segment != segment),
+    }
+    Unrecorded { waiting, seen }.into()
+  }
+}
+
+#[derive(Serialize)]
+struct HistoryArgs<'a> {
+  query: &'a HistoryQuery,
+}
+
+/// A session found in the history as it would have been handed over when it finished. How long it
+/// was planned to last isn't kept there, so that is taken from the settings as they are now.
+fn finished(segment: Segment, length: u64) -> Option<CompletedSession> {
+  let ended = segment.ended?;
+  Some(CompletedSession {
+    segment: segment.id,
+    started: segment.started,
+    ended,
+    length,
+    focused: segment.focused_until(ended),
+    issues: segment.issue_times(ended),
+    pauses: segment.pauses,
+  })
+}
+
+#[derive(Clone, Properties, PartialEq)]
+pub struct Props {
+  pub timer: TimerSnapshot,
+  /// The issue the session is being worked against
+  pub issue: Option<IssueSummary>,
+  /// What to do with the time worked beyond the session's length
+  pub overtime: OvertimeSettings,
+}
+
+/// The worklogs for a finished session: the time worked to its own issue, or only the planned
+/// length with the overtime going to another issue. Overtime starts once the planned length ends.
+fn worklogs(
+  session: &CompletedSession,
+  issue_key: String,
+  log: OvertimeLog,
+  split_issue: Option<String>,
+) -> Vec<WorkSegment> {
+  let work = |issue_key: String, started: u64, duration: u64| WorkSegment {
+    issue_key,
+    started,
+    duration,
+    comment: None,
+    segment: Some(session.segment),
+  };
+  let overtime = session.overtime();
+  match (log, split_issue) {
+    (OvertimeLog::Planned, _) => vec![work(issue_key, session.started, session.length)],
+    (OvertimeLog::Split, Some(other)) if overtime > 0 && other != issue_key => vec![
+      work(issue_key, session.started, session.length),
+      work(
+        other,
+        session.ended.saturating_sub(overtime * 1_000),
+        overtime,
+      ),
+    ],
+    _ => vec![work(issue_key, session.started, session.focused)],
+  }
+}
+
+/// Records a session in Jira as a worklog against the issue it was worked on as soon as it
+/// finishes. A session that ran into overtime is logged the way the settings say, asking first if
+/// they say to, and one that switched issues is split between them before it is logged. A session
+/// tracked without an issue goes to the one picked, waiting for one if there is none. Sessions are
+/// offered one at a time, and any that were never recorded because the window was closed or
+/// reloaded are found in the history when it loads. The worklogs go through the server's outbox, so
+/// they are only lost if the outbox can't be saved.
+#[function_component]
+pub fn WorklogRecorder(props: &Props) -> Html {
+  let guid = *use_memo((), |_| Uuid::new_v4());
+  let comment = use_state(String::new);
+  let recording = use_state(Recording::default);
+  let outbox = use_state(OutboxStatus::default);
+
+  let on_reply: Callback<LongRunnerMsg> = {
+    let (recording, outbox) = (recording.clone(), outbox.clone());
+    Callback::from(move |msg| match msg {
+      LongRunnerMsg::Reply(Response::Error(err)) => recording.set(Recording::Failed(err)),
+      LongRunnerMsg::Reply(Response::Rounded(_, outcome)) => {
+        recording.set(Recording::Rounded(outcome))
+      }
+      LongRunnerMsg::Reply(Response::Outbox(_, status))
+      | LongRunnerMsg::Emission(_, Payload::Outbox(status)) => outbox.set(status),
+      LongRunnerMsg::Emission(_, Payload::Worklog(worklog)) => {
+        recording.set(Recording::Recorded(worklog))
+      }
+      LongRunnerMsg::Emission(_, Payload::Error(err)) => {
+        warn!("The worklog could not be recorded: {}", err);
+        recording.set(Recording::Failed(err))
+      }
+      msg => info!("Worklog recorder received {:?}", msg),
+    })
+  };
+  let long_runner = use_long_runner(guid, on_reply);
+
+  // Pick up the worklogs still waiting from earlier, and follow the outbox from here on
+  {
+    let long_runner = long_runner.clone();
+    use_effect_with((), move |_| {
+      let message = RequestMessage::Outbox;
+      long_runner.send(&guid, to_value(&Request { message }).unwrap());
+    });
+  }
+
+  // Send a session's worklogs, with the comment typed so far
+  let send: Callback<Vec<WorkSegment>> = {
+    let long_runner = long_runner.clone();
+    let recording = recording.clone();
+    let comment = comment.trim().to_string();
+    Callback::from(move |segments: Vec<WorkSegment>| {
+      for mut segment in segments {
+        segment.comment = (!comment.is_empty()).then(|| comment.clone());
+        info!("Recording the finished session {:?}", segment);
+        let message = RequestMessage::RecordWork(segment);
+        long_runner.send(&guid, to_value(&Request { message }).unwrap());
+      }
+      recording.set(Recording::Sending);
+    })
+  };
+  let record: Callback<(CompletedSession, String, OvertimeLog, Option<String>)> = {
+    let send = send.clone();
+    Callback::from(move |(session, issue_key, log, split_issue)| {
+      send.emit(worklogs(&session, issue_key, log, split_issue))
+    })
+  };
+
+  // Work out how to log a finished session, asking about whatever the settings can't decide
+  let split_issue = use_state(String::new);
+  let offer: Callback<CompletedSession> = {
+    let (recording, split_issue) = (recording.clone(), split_issue.clone());
+    let (issue, overtime, record) = (props.issue.clone(), props.overtime.clone(), record.clone());
+    Callback::from(move |session: CompletedSession| {
+      if session.issues.len() > 1 {
+        recording.set(Recording::Splitting(session));
+        return;
+      }
+      // The session goes to the issue it was worked on, or the one picked now if it had none
+      let tracked = session
+        .issues
+        .first()
+        .and_then(|time| time.issue_key.clone());
+      let issue = match tracked.or_else(|| issue.as_ref().map(|issue| issue.key.clone())) {
+        Some(issue) => issue,
+        None => {
+          recording.set(Recording::NeedsIssue(session));
+          return;
+        }
+      };
+
+      // A split needs an issue to split onto, so without one it is asked about
+      let ask =
+        overtime.ask || (overtime.default == OvertimeLog::Split && overtime.split_issue.is_none());
+      if session.overtime() <= overtime.grace {
+        record.emit((session, issue, OvertimeLog::Actual, None));
+      } else if ask {
+        split_issue.set(overtime.split_issue.clone().unwrap_or_default());
+        recording.set(Recording::Overtime(session, issue));
+      } else {
+        let split_issue = overtime.split_issue.clone();
+        record.emit((session, issue, overtime.default, split_issue));
+      }
+    })
+  };
+
+  // Line up each session as soon as it ends. The one that had already ended when the window
+  // loaded is only lined up if the history shows it was never recorded.
+  let unrecorded = use_reducer_eq(Unrecorded::default);
+  let completed = props.timer.completed.clone();
+  let recorded = use_mut_ref(|| completed.clone());
+  {
+    let unrecorded = unrecorded.clone();
+    use_effect_with(completed, move |completed| {
+      let session = match completed {
+        Some(session) if Some(session) != recorded.borrow().as_ref() => session.clone(),
+        _ => return,
+      };
+      *recorded.borrow_mut() = Some(session.clone());
+      unrecorded.dispatch(Waiting::Add(vec![session]));
+    });
+  }
+
+  // Line up the sessions that ended without being recorded, as happens when the window is closed or
+  // reloaded before a session is logged. Recording a session notes it in the history straight away,
+  // so one with nothing noted never reached the outbox.
+  {
+    let (unrecorded, length) = (unrecorded.clone(), props.timer.settings.session_length);
+    use_effect_with((), move |_| {
+      let query = HistoryQuery {
+        from: Some((js_sys::Date::now() as u64).saturating_sub(UNRECORDED_WITHIN)),
+        unlogged: true,
+        ..HistoryQuery::default()
+      };
+      spawn_local(async move {
+        let args = to_value(&HistoryArgs { query: &query }).unwrap();
+        match crate::try_invoke("history_segments", args).await {
+          Ok(reply) => match from_value::<Vec<Segment>>(reply) {
+            Ok(segments) => unrecorded.dispatch(Waiting::Add(
+              segments
+                .into_iter()
+                .filter(|segment| segment.worklogs.is_empty())
+                .filter_map(|segment| finished(segment, length))
+                .collect(),
+            )),
+            Err(err) => warn!("Could not read the unrecorded sessions: {}", err),
+          },
+          Err(err) => warn!("Could not look for unrecorded sessions: {:?}", err),
+        }
+      });
+    });
+  }
+
+  // Offer the next session in line once nothing is waiting on an answer
+  {
+    let asking = matches!(
+      *recording,
+      Recording::Overtime(..) | Recording::Splitting(_) | Recording::NeedsIssue(_)
+    );
+    let (unrecorded, offer) = (unrecorded.clone(), offer.clone());
+    let next = unrecorded.waiting.first().cloned();
+    use_effect_with((next, asking), move |(next, asking)| {
+      if let (Some(session), false) = (next, asking) {
+        unrecorded.dispatch(Waiting::Take(session.segment));
+        offer.emit(session.clone());
+      }
+    });
+  }
+
+  // A session waiting for an issue is logged against the first one picked
+  {
+    let waiting = match &*recording {
+      Recording::NeedsIssue(session) => Some(session.clone()),
+      _ => None,
+    };
+    use_effect_with(props.issue.clone(), move |issue| {
+      if let (Some(session), Some(_)) = (waiting, issue) {
+        offer.emit(session);
+      }
+    });
+  }
+
+  let retry: Callback<()> = Callback::from(move |_| {
+    let message = RequestMessage::RetryOutbox;
+    long_runner.send(&guid, to_value(&Request { message }).unwrap());
+  });
+
+  let input = classes!("border-2", "p-1", "text-black");
+  let status = match &*recording {
+    Recording::Idle => html! {},
+    Recording::Sending => html! { <div>{"Waiting to record the session in Jira..."}</div> },
+    Recording::Recorded(worklog) => html! {
+      <div>
+        {format!("Logged {} minutes to {}", worklog.time_spent_seconds / 60, worklog.issue_key)}
+      </div>
+    },
+    Recording::Overtime(session, issue_key) => {
+      let choice = |log: OvertimeLog, label: String| {
+        let (record, split_issue) = (record.clone(), split_issue.clone());
+        let (session, issue_key) = (session.clone(), issue_key.clone());
+        let emphasis = (log == props.overtime.default).then_some("font-bold");
+        let disabled = log == OvertimeLog::Split && split_issue.trim().is_empty();
+        html! {
+          <button class={classes!("cursor-pointer", "border-2", "text-gray", "px-2", emphasis)}
+            {disabled} onclick={move |_| {
+              let other = split_issue.trim().to_uppercase();
+              let other = (!other.is_empty()).then_some(other);
+              record.emit((session.clone(), issue_key.clone(), log, other))
+            }}>
+            {label}
+          </button>
+        }
+      };
+      html! {
+        <div class={classes!("flex", "flex-col", "items-center", "space-y-2")}>
+          <div>
+            {format!("The session ran {} minutes over. What should be logged to {}?",
+              session.overtime() / 60, issue_key)}
+          </div>
+          <div class={classes!("flex", "flex-row", "space-x-2")}>
+            {choice(OvertimeLog::Planned, format!("Planned {} min", session.length / 60))}
+            {choice(OvertimeLog::Actual, format!("Actual {} min", session.focused / 60))}
+            {choice(OvertimeLog::Split, "Split the overtime onto".to_string())}
+            <input class={input.clone()} placeholder="Issue"
+              value={(*split_issue).clone()} oninput={bind(&split_issue)} />
+          </div>
+        </div>
+      }
+    }
+    Recording::Splitting(session) => html! {
+      <SplitDialog key={session.segment.to_string()} session={session.clone()}
+        on_submit={send.clone()} />
+    },
+    Recording::NeedsIssue(session) => html! {
+      <div>{format!("Pick an issue to log the {} minute session against", session.focused / 60)}</div>
+    },
+    Recording::Rounded(Outcome::Hold(seconds)) => html! {
+      <div>{format!("Holding {} minutes to log with the next session", seconds / 60)}</div>
+    },
+    Recording::Rounded(Outcome::OverCap) => html! {
+      <div>{"Not logged, as the project has reached its cap for today"}</div>
+    },
+    Recording::Rounded(_) => html! { <div>{"Not logged, as the session was too short"}</div> },
+    Recording::Failed(err) => html! {
+      <div class={classes!("text-red-600")}>{format!("Could not record the session: {}", err)}</div>
+    },
+  };
+
+  let waiting = match (outbox.pending, outbox.failed) {
+    (0, 0) => html! {},
+    (pending, failed) => html! {
+      <div class={classes!("flex", "flex-row", "items-center", "space-x-2", "text-sm")}>
+        <span title={outbox.last_error.clone().unwrap_or_default()}>
+          {format!("{} worklogs waiting to be sent", pending)}
+          if failed > 0 {
+            <span class={classes!("text-red-600")}>{format!(", {} refused by Jira", failed)}</span>
+          }
+        </span>
+        <button class={classes!("cursor-pointer", "border-2", "text-gray", "px-2")}
+          onclick={move |_| retry.emit(())}>
+          {"Retry"}
+        </button>
+      </div>
+    },
+  };
+
+  html! {
+    <div class={classes!("flex", "flex-col", "items-center", "space-y-2", "pb-4")}>
+      <input class={input} placeholder="Comment"
+        value={(*comment).clone()} oninput={bind(&comment)} />
+      {status}
+      {waiting}
+    </div>
+  }
+}
@@ -3,23 +3,35 @@
 //! These are tools that map messages into the frontend components. This is primarily a context for
 //! routing received emissions to the places that can make use of them.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock};
-use tracing::info;
+use std::rc::Rc;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::spawn_local;
-use yew::Callback;
+use yew::{hook, use_context, use_effect_with, Callback};
+
+use jiradoro_common::prelude::*;
+
+/// A message delivered to a component's reducer by the LongRunner
+#[derive(PartialEq, Clone, Debug)]
+pub enum LongRunnerMsg {
+  /// The server's reply to a request the component sent
+  Reply(Response),
+  /// A payload emitted by a process the component is subscribed to
+  Emission(Uuid, Payload),
+}
 
 /// A pub/sub system for the client side of the LongRunner. This handles much of the boilerplate for listening
 #[derive(Debug, Clone)]
 pub struct LongRunnerCtx {
   /// A routing table to map messages for each process id to all the reducers that care about that
   /// specific request id
-  forwards: Arc<RwLock<HashMap<Uuid, Vec<Uuid>>>>,
+  forwards: Rc<RefCell<HashMap<Uuid, Vec<Uuid>>>>,
 
   /// A map of component id to its reducer/dispatch
-  reducers: Arc<RwLock<HashMap<Uuid, Callback<String>>>>,
+  reducers: Rc<RefCell<HashMap<Uuid, Callback<LongRunnerMsg>>>>,
 }
 
 // This is essentially static with internal mutability, so the equality should always be true
@@ -29,64 +41,182 @@ impl PartialEq for LongRunnerCtx {
   }
 }
 
+impl Default for LongRunnerCtx {
+  fn default() -> LongRunnerCtx {
+    LongRunnerCtx::new()
+  }
+}
+
 impl LongRunnerCtx {
   pub fn new() -> LongRunnerCtx {
     LongRunnerCtx {
-      forwards: Arc::new(RwLock::new(HashMap::new())),
-      reducers: Arc::new(RwLock::new(HashMap::new())),
+      forwards: Rc::new(RefCell::new(HashMap::new())),
+      reducers: Rc::new(RefCell::new(HashMap::new())),
     }
   }
 
-  // Set
-  pub fn register(&self, guid: Uuid, reducer: Callback<String>) -> Uuid {
-    let mut reducers = self.reducers.write().unwrap();
+  /// Set the reducer messages for the component should be sent to
+  pub fn register(&self, guid: Uuid, reducer: Callback<LongRunnerMsg>) -> Uuid {
+    let mut reducers = self.reducers.borrow_mut();
     match reducers.insert(guid, reducer) {
-      Some(_) => info!("Updated the reducer for {}", guid),
+      Some(_) => debug!("Updated the reducer for {}", guid),
       None => info!("Inserted the reducer for new guid {}", guid),
     }
     guid
   }
 
-  pub fn update(&self, _component_id: Uuid, _reducer: String) {}
+  /// Replace the reducer of a component that has already been registered
+  pub fn update(&self, component_id: Uuid, reducer: Callback<LongRunnerMsg>) {
+    match self.reducers.borrow_mut().get_mut(&component_id) {
+      Some(current) => *current = reducer,
+      None => warn!("Cannot update {} as it is not registered", component_id),
+    }
+  }
 
-  pub fn deregister(&self, _component_id: Uuid) {}
+  /// Remove a component's reducer along with all of its subscriptions
+  pub fn deregister(&self, component_id: Uuid) {
+    info!("Deregistering the reducer for {}", component_id);
+    self.reducers.borrow_mut().remove(&component_id);
+    self.forwards.borrow_mut().retain(|_, components| {
+      components.retain(|guid| *guid != component_id);
+      !components.is_empty()
+    });
+  }
 
-  pub fn subscribe(&self, _proc_id: Uuid, _component_id: Uuid) {}
+  /// Forward any emissions from the process to the component's reducer
+  pub fn subscribe(&self, proc_id: Uuid, component_id: Uuid) {
+    let mut forwards = self.forwards.borrow_mut();
+    let components = forwards.entry(proc_id).or_default();
+    if !components.contains(&component_id) {
+      info!("Subscribing {} to process {}", component_id, proc_id);
+      components.push(component_id);
+    }
+  }
 
-  pub fn unsubscribe(&self, _proc_id: Uuid, _component_id: Uuid) {}
+  /// Stop forwarding the process's emissions to the component
+  pub fn unsubscribe(&self, proc_id: Uuid, component_id: Uuid) {
+    let mut forwards = self.forwards.borrow_mut();
+    if let Some(components) = forwards.get_mut(&proc_id) {
+      components.retain(|guid| *guid != component_id);
+      if components.is_empty() {
+        forwards.remove(&proc_id);
+      }
+    }
+  }
 
   /// Sends a message through the IPC layer to the server side LongRunner. This creates a message ID
-  /// used for routing any completed replies back to the sender's reducer.
-  pub fn send(&self, sender: &Uuid, msg: JsValue) -> () {
+  /// used for routing any completed replies back to the sender's reducer. A sender that is no
+  /// longer registered, like a component being unmounted, still has the message sent, but nothing
+  /// is left to hear the reply.
+  pub fn send(&self, sender: &Uuid, msg: JsValue) {
     let msg_id = Uuid::new_v4();
-    // Get the reducer for the sender.
-    let reducers = self.reducers.read().unwrap();
-
-    let _reducer = match reducers.get(sender) {
-      Some(_) => {
-        info!("Retrieved the reducer for {}", sender)
-      }
-      None => panic!("{} is not registered with the LongRunner", sender),
-    };
-
     info!(
       "Sending a new message for {} with id {}:\n{:?}",
       sender, msg_id, msg
     );
 
     // Add a listener for the result and map them to the reducer
-    let mut forwards = self.forwards.write().unwrap();
-    let _ = forwards.insert(msg_id, vec![sender.clone()]);
-    drop(forwards);
+    if self.reducers.borrow().contains_key(sender) {
+      let _ = self.forwards.borrow_mut().insert(msg_id, vec![*sender]);
+    } else {
+      warn!(
+        "{} is not registered with the LongRunner, so the reply will be dropped",
+        sender
+      );
+    }
 
     // Send the mesage to the server side
+    let long_runner = self.clone();
     spawn_local(async move {
-      info!("About to 'call_server::Heartbeat': {:#?}", msg);
+      info!("About to 'call_server': {:#?}", msg);
       let reply = crate::invoke("call_server", msg).await;
 
       info!("Server sent the reply {:#?}", reply);
+      match serde_wasm_bindgen::from_value::<Response>(reply) {
+        Ok(response) => long_runner.reply(msg_id, response),
+        Err(err) => {
+          warn!("Could not read the reply to {}: {}", msg_id, err);
+          long_runner.forwards.borrow_mut().remove(&msg_id);
+        }
+      }
+    });
+  }
+
+  /// Deliver the reply to a sent message back to the reducers waiting on it. Processes named in the
+  /// reply are subscribed to as well, so their later emissions find their way to the same reducers.
+  fn reply(&self, msg_id: Uuid, response: Response) {
+    let components = self
+      .forwards
+      .borrow_mut()
+      .remove(&msg_id)
+      .unwrap_or_default();
+
+    if let Some(proc_id) = response.process() {
+      for component_id in components.iter() {
+        self.subscribe(proc_id, *component_id);
+      }
+    }
+
+    let reducers: Vec<Callback<LongRunnerMsg>> = {
+      let reducers = self.reducers.borrow();
+      components
+        .iter()
+        .filter_map(|component_id| reducers.get(component_id).cloned())
+        .collect()
+    };
+    for reducer in reducers {
+      reducer.emit(LongRunnerMsg::Reply(response.clone()));
+    }
+  }
+
+  /// Deliver an emission from the server to every reducer subscribed to its process
+  pub fn receive(&self, emission: Emission) {
+    let payload = match emission.message {
+      Response::Payload(payload) => payload,
+      message => {
+        warn!(
+          "Emission for {} had no payload: {:?}",
+          emission.guid, message
+        );
+        return;
+      }
+    };
+
+    // Collect the reducers first so a reducer is free to change the subscriptions
+    let reducers: Vec<Callback<LongRunnerMsg>> = {
+      let forwards = self.forwards.borrow();
+      let reducers = self.reducers.borrow();
+      forwards
+        .get(&emission.guid)
+        .into_iter()
+        .flatten()
+        .filter_map(|component_id| reducers.get(component_id).cloned())
+        .collect()
+    };
+
+    if reducers.is_empty() {
+      debug!("No components are subscribed to {}", emission.guid);
+    }
+    for reducer in reducers {
+      reducer.emit(LongRunnerMsg::Emission(emission.guid, payload.clone()));
+    }
+  }
+}
+
+/// Registers the reducer for a component with the LongRunner, keeping it current on every render
+/// and removing it along with its subscriptions when the component is unmounted.
+#[hook]
+pub fn use_long_runner(guid: Uuid, reducer: Callback<LongRunnerMsg>) -> LongRunnerCtx {
+  let long_runner = use_context::<LongRunnerCtx>().expect("LongRunner context not found");
+  long_runner.register(guid, reducer);
+
+  {
+    let long_runner = long_runner.clone();
+    use_effect_with(guid, move |guid| {
+      let guid = *guid;
+      move || long_runner.deregister(guid)
     });
   }
 
-  pub fn receive(&self) {}
+  long_runner
 }
@@ -7,15 +7,27 @@ pub mod components;
 pub mod longrunner;
 
 pub mod prelude {
-  pub use super::longrunner::LongRunnerCtx;
+  pub use super::longrunner::{use_long_runner, LongRunnerCtx, LongRunnerMsg};
 }
 
 mod helpers {
-  pub fn format_time(seconds: u32) -> String {
+  use web_sys::HtmlInputElement;
+  use yew::{Callback, InputEvent, TargetCast, UseStateHandle};
+
+  pub fn format_time(seconds: u64) -> String {
     let minutes = seconds / 60;
     let seconds = seconds % 60;
     format!("{:02}:{:02}", minutes, seconds)
   }
+
+  /// Keep a text field's state in step with what is typed into it
+  pub fn bind(field: &UseStateHandle<String>) -> Callback<InputEvent> {
+    let field = field.clone();
+    Callback::from(move |event: InputEvent| {
+      let input: HtmlInputElement = event.target_unchecked_into();
+      field.set(input.value());
+    })
+  }
 }
 
 /// Enable tracing to dump to the console
@@ -66,6 +78,9 @@ mod logger {
 extern "C" {
   #[wasm_bindgen(js_namespace = ["window.__TAURI__.core"])]
   async fn invoke(cmd: &str, args: JsValue) -> JsValue;
+  /// Like `invoke`, but hands back the error when the command fails instead of throwing it
+  #[wasm_bindgen(js_namespace = ["window.__TAURI__.core"], js_name = "invoke", catch)]
+  async fn try_invoke(cmd: &str, args: JsValue) -> Result<JsValue, JsValue>;
   #[wasm_bindgen(js_namespace = ["window.__TAURI__.event"], js_name = "listen")]
   fn listen_(event: &str, handler: &Closure<dyn FnMut(JsValue)>) -> Promise;
 }
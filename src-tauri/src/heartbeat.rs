@@ -0,0 +1,65 @@
+//! A LongRunner process that emits a tick on a fixed interval until it is cancelled. It is used by
+//! the frontend as a liveness check that the backend is still responsive.
+
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::info;
+
+use crate::longrunner::prelude::*;
+use jiradoro_common::prelude::*;
+
+pub struct Heartbeat {
+  /// The time between each beat
+  pub period: Duration,
+}
+
+impl Default for Heartbeat {
+  fn default() -> Heartbeat {
+    Heartbeat {
+      period: Duration::from_secs(1),
+    }
+  }
+}
+
+#[async_trait]
+impl LongRunnerProcess for Heartbeat {
+  /// The total number of beats emitted before the heartbeat was stopped
+  type Output = u64;
+  type Error = Infallible;
+
+  async fn start(self, tools: ProcessTools) -> Result<u64, Infallible> {
+    let mut ticker = interval(self.period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // The first tick of an interval completes immediately, so skip it to beat after one period
+    ticker.tick().await;
+
+    let mut count = 0;
+    loop {
+      tokio::select! {
+        _ = tools.cancellation_token.cancelled() => break,
+        _ = ticker.tick() => {
+          count += 1;
+          let tick = HeartbeatTick {
+            count,
+            timestamp: SystemTime::now()
+              .duration_since(UNIX_EPOCH)
+              .map(|elapsed| elapsed.as_millis() as u64)
+              .unwrap_or_default(),
+          };
+          tools.emit(Payload::Heartbeat(tick)).await;
+        }
+      }
+    }
+
+    info!("Heartbeat {} stopped after {} beats", tools.guid, count);
+    tools.log(format!("Stopped after {} beats", count)).await;
+    Ok(count)
+  }
+
+  fn grace_period(&self) -> Option<Duration> {
+    Some(self.period)
+  }
+}
@@ -0,0 +1,592 @@
+//! The local history of every session and break, kept in an SQLite database in the app's data
+//! directory. It is an audit trail that doesn't depend on Jira: each work session records the
+//! issues it tracked and what it was put forward to Jira as, one worklog for each issue its time
+//! went to, so anything that never made it into Jira can be found and reconciled later.
+//!
+//! The schema is brought up to date when the database is opened by running each migration it has
+//! not seen yet, tracked with SQLite's `user_version`.
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, Row};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::info;
+use uuid::Uuid;
+
+use jiradoro_common::prelude::*;
+
+/// The migration at each index takes the schema from version `index` to `index + 1`
+const MIGRATIONS: &[&str] = &[
+  "
+  CREATE TABLE segments (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    started INTEGER NOT NULL,
+    ended INTEGER,
+    issue_key TEXT,
+    completed INTEGER NOT NULL DEFAULT 0,
+    worklog_id TEXT
+  );
+  CREATE INDEX segments_started ON segments (started);
+
+  CREATE TABLE pauses (
+    segment_id TEXT NOT NULL REFERENCES segments (id) ON DELETE CASCADE,
+    started INTEGER NOT NULL,
+    ended INTEGER NOT NULL
+  );
+  CREATE INDEX pauses_segment ON pauses (segment_id);
+",
+  "
+  ALTER TABLE segments ADD COLUMN logged INTEGER;
+  ALTER TABLE segments ADD COLUMN held INTEGER NOT NULL DEFAULT 0;
+",
+  "
+  CREATE TABLE worklogs (
+    segment_id TEXT NOT NULL REFERENCES segments (id) ON DELETE CASCADE,
+    issue_key TEXT NOT NULL,
+    started INTEGER NOT NULL,
+    logged INTEGER NOT NULL DEFAULT 0,
+    held INTEGER NOT NULL DEFAULT 0,
+    worklog_id TEXT,
+    PRIMARY KEY (segment_id, issue_key)
+  );
+  CREATE INDEX worklogs_issue ON worklogs (issue_key);
+
+  INSERT INTO worklogs (segment_id, issue_key, started, logged, held, worklog_id)
+    SELECT id, issue_key, started, COALESCE(logged, 0), held, worklog_id FROM segments
+    WHERE kind = 'work' AND issue_key IS NOT NULL
+      AND (logged IS NOT NULL OR worklog_id IS NOT NULL);
+  ALTER TABLE segments DROP COLUMN worklog_id;
+  ALTER TABLE segments DROP COLUMN logged;
+  ALTER TABLE segments DROP COLUMN held;
+",
+  "
+  CREATE TABLE switches (
+    segment_id TEXT NOT NULL REFERENCES segments (id) ON DELETE CASCADE,
+    at INTEGER NOT NULL,
+    issue_key TEXT
+  );
+  CREATE INDEX switches_segment ON switches (segment_id);
+",
+  "
+  ALTER TABLE worklogs ADD COLUMN merged_into TEXT;
+",
+];
+
+fn kind_name(kind: SegmentKind) -> &'static str {
+  match kind {
+    SegmentKind::Work => "work",
+    SegmentKind::Break => "break",
+    SegmentKind::LongBreak => "long_break",
+  }
+}
+
+/// A column that could not be turned back into the value it was saved from
+fn invalid(column: usize, err: impl std::error::Error + Send + Sync + 'static) -> rusqlite::Error {
+  rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(err))
+}
+
+#[derive(Debug)]
+struct UnknownKind(String);
+
+impl std::fmt::Display for UnknownKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?} is not a kind of segment", self.0)
+  }
+}
+
+impl std::error::Error for UnknownKind {}
+
+/// Read a segment, without its pauses, switches or worklogs, out of a row of the segments table
+fn segment(row: &Row) -> rusqlite::Result<Segment> {
+  let id: String = row.get(0)?;
+  let kind: String = row.get(1)?;
+  Ok(Segment {
+    id: Uuid::parse_str(&id).map_err(|err| invalid(0, err))?,
+    kind: match kind.as_str() {
+      "work" => SegmentKind::Work,
+      "break" => SegmentKind::Break,
+      "long_break" => SegmentKind::LongBreak,
+      _ => return Err(invalid(1, UnknownKind(kind))),
+    },
+    started: row.get(2)?,
+    ended: row.get(3)?,
+    pauses: Vec::new(),
+    issue_key: row.get(4)?,
+    switches: Vec::new(),
+    completed: row.get(5)?,
+    worklogs: Vec::new(),
+  })
+}
+
+pub struct History {
+  connection: Mutex<Connection>,
+}
+
+impl History {
+  /// Open the history stored at the path, creating it if there isn't one yet
+  pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<History> {
+    info!("Opening the history at {}", path.as_ref().display());
+    History::setup(Connection::open(path)?)
+  }
+
+  fn setup(mut connection: Connection) -> rusqlite::Result<History> {
+    connection.pragma_update(None, "foreign_keys", true)?;
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+      info!("Migrating the history to version {}", index + 1);
+      let transaction = connection.transaction()?;
+      transaction.execute_batch(migration)?;
+      transaction.pragma_update(None, "user_version", index + 1)?;
+      transaction.commit()?;
+    }
+    Ok(History {
+      connection: Mutex::new(connection),
+    })
+  }
+
+  /// Add a segment to the history, or bring the one already there up to date. What it was put
+  /// forward to Jira as is left alone, as that is only ever set through `rounded` and `logged`.
+  pub fn save(&self, segment: &Segment) -> rusqlite::Result<()> {
+    let mut connection = self.connection.lock().unwrap();
+    let transaction = connection.transaction()?;
+    let id = segment.id.to_string();
+    transaction.execute(
+      "INSERT INTO segments (id, kind, started, ended, issue_key, completed)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (id) DO UPDATE SET
+          kind = excluded.kind,
+          started = excluded.started,
+          ended = excluded.ended,
+          issue_key = excluded.issue_key,
+          completed = excluded.completed",
+      params![
+        id,
+        kind_name(segment.kind),
+        segment.started,
+        segment.ended,
+        segment.issue_key,
+        segment.completed
+      ],
+    )?;
+    transaction.execute("DELETE FROM pauses WHERE segment_id = ?1", params![id])?;
+    for pause in &segment.pauses {
+      transaction.execute(
+        "INSERT INTO pauses (segment_id, started, ended) VALUES (?1, ?2, ?3)",
+        params![id, pause.started, pause.ended],
+      )?;
+    }
+    transaction.execute("DELETE FROM switches WHERE segment_id = ?1", params![id])?;
+    for switch in &segment.switches {
+      transaction.execute(
+        "INSERT INTO switches (segment_id, at, issue_key) VALUES (?1, ?2, ?3)",
+        params![id, switch.at, switch.from],
+      )?;
+    }
+    transaction.commit()
+  }
+
+  /// Note the worklog Jira recorded the work as, returning whether its session was found
+  pub fn logged(&self, work: &WorkSegment, worklog_id: &str) -> rusqlite::Result<bool> {
+    let segment = match work.segment {
+      Some(segment) => segment,
+      None => return Ok(false),
+    };
+    let connection = self.connection.lock().unwrap();
+    let updated = connection.execute(
+      "INSERT INTO worklogs (segment_id, issue_key, started, logged, worklog_id)
+        SELECT id, ?2, ?3, ?4, ?5 FROM segments WHERE id = ?1
+        ON CONFLICT (segment_id, issue_key) DO UPDATE SET worklog_id = excluded.worklog_id",
+      params![
+        segment.to_string(),
+        work.issue_key,
+        work.started,
+        work.duration,
+        worklog_id
+      ],
+    )?;
+    Ok(updated > 0)
+  }
+
+  /// What has already been logged to the issue's project between the two times, and what is held
+  /// on the issue itself, for the rounding to take into account
+  pub fn ledger(&self, issue_key: &str, from: u64, to: u64) -> rusqlite::Result<Ledger> {
+    let connection = self.connection.lock().unwrap();
+    let logged_today = connection.query_row(
+      "SELECT COALESCE(SUM(logged), 0) FROM worklogs
+        WHERE NOT held AND merged_into IS NULL AND started >= ?2 AND started < ?3
+          AND substr(issue_key, 1, length(?1) + 1) = ?1 || '-'",
+      params![project_key(issue_key), from, to],
+      |row| row.get(0),
+    )?;
+    let held = connection.query_row(
+      "SELECT COALESCE(SUM(logged), 0) FROM worklogs WHERE held AND issue_key = ?1",
+      params![issue_key],
+      |row| row.get(0),
+    )?;
+    Ok(Ledger { logged_today, held })
+  }
+
+  /// Note what the rounding made of the work. Logging it takes in the work held on the issue, which
+  /// keeps its seconds and is marked as merged into the session that logged it. Work that is turned
+  /// away leaves the held work waiting for the next session on the issue.
+  pub fn rounded(&self, work: &WorkSegment, outcome: Outcome) -> rusqlite::Result<()> {
+    let mut connection = self.connection.lock().unwrap();
+    let transaction = connection.transaction()?;
+    let (logged, held) = match outcome {
+      Outcome::Log(seconds) => (seconds, false),
+      Outcome::Hold(seconds) => (seconds, true),
+      Outcome::Short | Outcome::OverCap => (0, false),
+    };
+    if let (Outcome::Log(_), Some(segment)) = (outcome, work.segment) {
+      transaction.execute(
+        "UPDATE worklogs SET held = 0, merged_into = ?2 WHERE held AND issue_key = ?1",
+        params![work.issue_key, segment.to_string()],
+      )?;
+    }
+    if let Some(segment) = work.segment {
+      transaction.execute(
+        "INSERT INTO worklogs (segment_id, issue_key, started, logged, held)
+          SELECT id, ?2, ?3, ?4, ?5 FROM segments WHERE id = ?1
+          ON CONFLICT (segment_id, issue_key) DO UPDATE SET
+            started = excluded.started,
+            logged = excluded.logged,
+            held = excluded.held",
+        params![
+          segment.to_string(),
+          work.issue_key,
+          work.started,
+          logged,
+          held
+        ],
+      )?;
+    }
+    transaction.commit()
+  }
+
+  /// The segments matching the query, oldest first
+  pub fn query(&self, query: &HistoryQuery) -> rusqlite::Result<Vec<Segment>> {
+    let connection = self.connection.lock().unwrap();
+    let mut statement = connection.prepare(
+      "SELECT id, kind, started, ended, issue_key, completed
+        FROM segments
+        WHERE (?1 IS NULL OR started >= ?1)
+          AND (?2 IS NULL OR started < ?2)
+          AND (?3 IS NULL OR issue_key = ?3)
+          AND (NOT ?4 OR (kind = 'work' AND completed AND NOT EXISTS (
+            SELECT 1 FROM worklogs WHERE segment_id = segments.id AND worklog_id IS NOT NULL
+          )))
+        ORDER BY started",
+    )?;
+    let segments = statement
+      .query_map(
+        params![query.from, query.to, query.issue_key, query.unlogged],
+        segment,
+      )?
+      .collect::<rusqlite::Result<Vec<Segment>>>()?;
+    segments
+      .into_iter()
+      .map(|found| History::with_details(&connection, found))
+      .collect()
+  }
+
+  /// Fill in the segment's pauses, switches and worklogs
+  fn with_details(connection: &Connection, mut segment: Segment) -> rusqlite::Result<Segment> {
+    let id = segment.id.to_string();
+    let mut statement = connection
+      .prepare_cached("SELECT started, ended FROM pauses WHERE segment_id = ?1 ORDER BY started")?;
+    segment.pauses = statement
+      .query_map(params![id], |row| {
+        Ok(Pause {
+          started: row.get(0)?,
+          ended: row.get(1)?,
+        })
+      })?
+      .collect::<rusqlite::Result<Vec<Pause>>>()?;
+    let mut statement = connection
+      .prepare_cached("SELECT at, issue_key FROM switches WHERE segment_id = ?1 ORDER BY at")?;
+    segment.switches = statement
+      .query_map(params![id], |row| {
+        Ok(IssueSwitch {
+          at: row.get(0)?,
+          from: row.get(1)?,
+        })
+      })?
+      .collect::<rusqlite::Result<Vec<IssueSwitch>>>()?;
+    let mut statement = connection.prepare_cached(
+      "SELECT issue_key, started, logged, held, worklog_id, merged_into FROM worklogs
+        WHERE segment_id = ?1 ORDER BY started, issue_key",
+    )?;
+    segment.worklogs = statement
+      .query_map(params![id], |row| {
+        let merged_into: Option<String> = row.get(5)?;
+        Ok(SegmentWorklog {
+          issue_key: row.get(0)?,
+          started: row.get(1)?,
+          logged: row.get(2)?,
+          held: row.get(3)?,
+          worklog_id: row.get(4)?,
+          merged_into: merged_into
+            .map(|merged_into| Uuid::parse_str(&merged_into).map_err(|err| invalid(5, err)))
+            .transpose()?,
+        })
+      })?
+      .collect::<rusqlite::Result<Vec<SegmentWorklog>>>()?;
+    Ok(segment)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rusqlite::OptionalExtension;
+
+  impl History {
+    fn get(&self, id: Uuid) -> rusqlite::Result<Option<Segment>> {
+      let connection = self.connection.lock().unwrap();
+      let found = connection
+        .query_row(
+          "SELECT id, kind, started, ended, issue_key, completed FROM segments WHERE id = ?1",
+          params![id.to_string()],
+          segment,
+        )
+        .optional()?;
+      match found {
+        Some(found) => Ok(Some(History::with_details(&connection, found)?)),
+        None => Ok(None),
+      }
+    }
+  }
+
+  fn history() -> History {
+    History::setup(Connection::open_in_memory().unwrap()).unwrap()
+  }
+
+  fn session(started: u64, issue_key: &str) -> Segment {
+    let mut session = Segment::new(SegmentKind::Work, started, Some(issue_key.to_string()));
+    session.ended = Some(started + 25 * 60 * 1_000);
+    session.completed = true;
+    session
+  }
+
+  /// The whole of a session, put forward to its own issue
+  fn work(session: &Segment) -> WorkSegment {
+    WorkSegment {
+      issue_key: session.issue_key.clone().unwrap(),
+      started: session.started,
+      duration: 25 * 60,
+      comment: None,
+      segment: Some(session.id),
+    }
+  }
+
+  #[test]
+  fn saves_segments_with_their_pauses() {
+    let history = history();
+    let mut segment = session(1_000, "JRD-1");
+    segment.pauses = vec![
+      Pause {
+        started: 60_000,
+        ended: 90_000,
+      },
+      Pause {
+        started: 120_000,
+        ended: 125_000,
+      },
+    ];
+    history.save(&segment).unwrap();
+    assert_eq!(history.get(segment.id).unwrap(), Some(segment.clone()));
+
+    // Saving it again replaces it, pauses, switches and all
+    segment.switches.push(IssueSwitch {
+      at: 100_000,
+      from: Some("JRD-1".to_string()),
+    });
+    segment.pauses.pop();
+    segment.issue_key = Some("JRD-2".to_string());
+    history.save(&segment).unwrap();
+    assert_eq!(history.get(segment.id).unwrap(), Some(segment));
+  }
+
+  #[test]
+  fn keeps_the_worklog_when_a_segment_is_saved_again() {
+    let history = history();
+    let mut segment = session(1_000, "JRD-1");
+    history.save(&segment).unwrap();
+    assert!(history.logged(&work(&segment), "100028").unwrap());
+    let unknown = work(&session(1_000, "JRD-1"));
+    assert!(!history.logged(&unknown, "100029").unwrap());
+
+    segment.ended = Some(2_000_000);
+    history.save(&segment).unwrap();
+    let saved = history.get(segment.id).unwrap().unwrap();
+    assert_eq!(saved.worklogs[0].worklog_id.as_deref(), Some("100028"));
+    assert_eq!(saved.ended, Some(2_000_000));
+  }
+
+  #[test]
+  fn keeps_a_worklog_for_each_issue_a_session_went_to() {
+    let history = history();
+    let segment = session(1_000, "JRD-1");
+    history.save(&segment).unwrap();
+    let planned = work(&segment);
+    let overtime = WorkSegment {
+      issue_key: "OPS-7".to_string(),
+      started: 1_000 + 25 * 60 * 1_000,
+      duration: 5 * 60,
+      ..planned.clone()
+    };
+    history.rounded(&planned, Outcome::Log(25 * 60)).unwrap();
+    history.rounded(&overtime, Outcome::Log(5 * 60)).unwrap();
+    history.logged(&overtime, "100029").unwrap();
+
+    let saved = history.get(segment.id).unwrap().unwrap();
+    let pieces: Vec<_> = saved
+      .worklogs
+      .iter()
+      .map(|piece| {
+        (
+          piece.issue_key.as_str(),
+          piece.logged,
+          piece.worklog_id.as_deref(),
+        )
+      })
+      .collect();
+    assert_eq!(
+      pieces,
+      vec![("JRD-1", 25 * 60, None), ("OPS-7", 5 * 60, Some("100029"))]
+    );
+    // Each issue's project is charged with its own share
+    assert_eq!(
+      history
+        .ledger("OPS-7", 0, u32::MAX as u64)
+        .unwrap()
+        .logged_today,
+      5 * 60
+    );
+  }
+
+  #[test]
+  fn held_work_waits_until_a_session_logs_it() {
+    let history = history();
+    let rounded = |started: u64, outcome: Outcome| -> Segment {
+      let segment = session(started, "JRD-1");
+      history.save(&segment).unwrap();
+      history.rounded(&work(&segment), outcome).unwrap();
+      segment
+    };
+    let held = |history: &History| history.ledger("JRD-1", 0, u32::MAX as u64).unwrap();
+
+    let short = rounded(1_000, Outcome::Hold(4 * 60));
+    rounded(2_000, Outcome::OverCap);
+    rounded(3_000, Outcome::Short);
+    // Work turned away doesn't take the held work with it
+    assert_eq!(held(&history).held, 4 * 60);
+
+    let merged = rounded(4_000, Outcome::Log(15 * 60));
+    assert_eq!(
+      held(&history),
+      Ledger {
+        logged_today: 15 * 60,
+        held: 0,
+      }
+    );
+    // The held work keeps its seconds, and says which session it was logged with
+    let saved = history.get(short.id).unwrap().unwrap();
+    assert_eq!(
+      saved.worklogs,
+      vec![SegmentWorklog {
+        issue_key: "JRD-1".to_string(),
+        started: 1_000,
+        logged: 4 * 60,
+        held: false,
+        worklog_id: None,
+        merged_into: Some(merged.id),
+      }]
+    );
+  }
+
+  #[test]
+  fn moves_worklogs_out_of_the_segments() {
+    let mut connection = Connection::open_in_memory().unwrap();
+    let transaction = connection.transaction().unwrap();
+    transaction.execute_batch(MIGRATIONS[0]).unwrap();
+    transaction.execute_batch(MIGRATIONS[1]).unwrap();
+    transaction.pragma_update(None, "user_version", 2).unwrap();
+    transaction
+      .execute(
+        "INSERT INTO segments (id, kind, started, ended, issue_key, completed, worklog_id, logged)
+          VALUES (?1, 'work', 1000, 1501000, 'JRD-1', 1, '100028', 1500)",
+        params![Uuid::nil().to_string()],
+      )
+      .unwrap();
+    transaction.commit().unwrap();
+
+    let history = History::setup(connection).unwrap();
+    let saved = history.get(Uuid::nil()).unwrap().unwrap();
+    assert_eq!(
+      saved.worklogs,
+      vec![SegmentWorklog {
+        issue_key: "JRD-1".to_string(),
+        started: 1_000,
+        logged: 1_500,
+        held: false,
+        worklog_id: Some("100028".to_string()),
+        merged_into: None,
+      }]
+    );
+  }
+
+  #[test]
+  fn queries_by_time_issue_and_whether_it_was_logged() {
+    let history = history();
+    let first = session(1_000, "JRD-1");
+    let second = session(2_000, "JRD-2");
+    let third = session(3_000, "JRD-1");
+    let mut cut_short = Segment::new(SegmentKind::Work, 4_000, Some("JRD-1".to_string()));
+    cut_short.ended = Some(5_000);
+    let rest = Segment::new(SegmentKind::Break, 5_000, None);
+    for segment in [&third, &first, &second, &cut_short, &rest] {
+      history.save(segment).unwrap();
+    }
+    history.logged(&work(&first), "100028").unwrap();
+
+    let ids = |query: HistoryQuery| -> Vec<Uuid> {
+      let segments = history.query(&query).unwrap();
+      segments.into_iter().map(|segment| segment.id).collect()
+    };
+    assert_eq!(
+      ids(HistoryQuery::default()),
+      vec![first.id, second.id, third.id, cut_short.id, rest.id]
+    );
+    let between = HistoryQuery {
+      from: Some(2_000),
+      to: Some(4_000),
+      ..HistoryQuery::default()
+    };
+    assert_eq!(ids(between), vec![second.id, third.id]);
+    let issue = HistoryQuery {
+      issue_key: Some("JRD-1".to_string()),
+      ..HistoryQuery::default()
+    };
+    assert_eq!(ids(issue), vec![first.id, third.id, cut_short.id]);
+    let unlogged = HistoryQuery {
+      unlogged: true,
+      ..HistoryQuery::default()
+    };
+    assert_eq!(ids(unlogged), vec![second.id, third.id]);
+  }
+
+  #[test]
+  fn history_survives_a_restart() {
+    let file = std::env::temp_dir().join(format!("jiradoro-history-{}.sqlite3", Uuid::new_v4()));
+    let segment = session(1_000, "JRD-1");
+    History::open(&file).unwrap().save(&segment).unwrap();
+
+    // Opening it again finds the schema up to date and the segment still there
+    let reopened = History::open(&file).unwrap();
+    assert_eq!(reopened.get(segment.id).unwrap(), Some(segment));
+    drop(reopened);
+    std::fs::remove_file(file).unwrap();
+  }
+}
@@ -0,0 +1,654 @@
+//! A client for the parts of the Jira Cloud REST API that the app talks to.
+//!
+//! Credentials only ever live on the server side. The frontend hands them over once when logging
+//! in and afterwards only sees the resulting `JiraUser`.
+
+use chrono::{DateTime, Utc};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use tracing::warn;
+use uuid::Uuid;
+
+use jiradoro_common::prelude::*;
+
+pub mod oauth;
+use oauth::OAuthTokens;
+
+/// How the client proves who it is to Jira
+#[derive(Clone, Debug)]
+pub enum Auth {
+  /// Basic auth with the account's email and a personal API token
+  ApiToken { email: String, token: String },
+  /// A bearer token from an OAuth 2.0 login, refreshed as it expires
+  OAuth(OAuthTokens),
+}
+
+/// The ways a request to Jira can fail
+#[derive(PartialEq, Clone, Debug)]
+pub enum JiraError {
+  /// The site url could not be used to build requests
+  InvalidSite(String),
+  /// The text is not shaped like an issue key, so it can't go in a request
+  InvalidIssueKey(String),
+  /// The request never received a response
+  Http(String),
+  /// Jira did not accept the credentials
+  Unauthorized,
+  /// Jira responded with a status the client did not expect
+  Status { code: u16, message: String },
+  /// Jira is turning requests away until the given number of seconds has passed
+  RateLimited { retry_after: Option<u64> },
+  /// The response body was not what the client expected
+  Decode(String),
+  /// The OAuth 2.0 login could not be completed
+  OAuth(String),
+}
+
+impl Display for JiraError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      JiraError::InvalidSite(site) => write!(f, "'{}' is not a valid Jira site", site),
+      JiraError::InvalidIssueKey(key) => write!(f, "'{}' is not a Jira issue key", key),
+      JiraError::Http(err) => write!(f, "Could not reach Jira: {}", err),
+      JiraError::Unauthorized => write!(f, "Jira rejected the credentials"),
+      JiraError::Status { code, message } => write!(f, "Jira responded with {}: {}", code, message),
+      JiraError::RateLimited { retry_after } => match retry_after {
+        Some(seconds) => write!(f, "Jira asked to wait {} seconds between requests", seconds),
+        None => write!(f, "Jira is limiting how often requests can be made"),
+      },
+      JiraError::Decode(err) => write!(f, "Could not read the response from Jira: {}", err),
+      JiraError::OAuth(err) => write!(f, "Could not log in with Atlassian: {}", err),
+    }
+  }
+}
+
+impl From<reqwest::Error> for JiraError {
+  fn from(err: reqwest::Error) -> JiraError {
+    match err.is_decode() {
+      true => JiraError::Decode(err.to_string()),
+      false => JiraError::Http(err.to_string()),
+    }
+  }
+}
+
+/// The account details returned by `/rest/api/3/myself`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Myself {
+  account_id: String,
+  display_name: String,
+  email_address: Option<String>,
+  #[serde(default)]
+  avatar_urls: HashMap<String, String>,
+}
+
+impl From<Myself> for JiraUser {
+  fn from(myself: Myself) -> JiraUser {
+    // Avatars are keyed by their dimensions, such as "48x48", so take the biggest one available
+    let avatar_url = myself
+      .avatar_urls
+      .iter()
+      .max_by_key(|(size, _)| {
+        size
+          .split('x')
+          .next()
+          .and_then(|width| width.parse::<u32>().ok())
+      })
+      .map(|(_, url)| url.clone());
+
+    JiraUser {
+      account_id: myself.account_id,
+      display_name: myself.display_name,
+      email_address: myself.email_address,
+      avatar_url,
+    }
+  }
+}
+
+/// The worklog property holding the id the app recorded the worklog under
+const WORKLOG_PROPERTY: &str = "jiradoro";
+
+/// How long before a worklog's start to look for it from, in milliseconds. Jira may keep the start
+/// less precisely than it was sent, and may not count a worklog starting right on the bound.
+const FIND_MARGIN: u64 = 60_000;
+
+/// A worklog as returned by `/rest/api/3/issue/{key}/worklog`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IssueWorklog {
+  id: String,
+  time_spent_seconds: u64,
+  #[serde(default)]
+  properties: Vec<WorklogProperty>,
+}
+
+#[derive(Deserialize)]
+struct WorklogProperty {
+  key: String,
+  value: serde_json::Value,
+}
+
+impl IssueWorklog {
+  /// Whether the app recorded this worklog under the id
+  fn recorded_as(&self, id: Uuid) -> bool {
+    self.properties.iter().any(|property| {
+      property.key == WORKLOG_PROPERTY && property.value["id"] == id.to_string().as_str()
+    })
+  }
+
+  fn summarize(self, issue_key: &str) -> Worklog {
+    Worklog {
+      id: self.id,
+      issue_key: issue_key.to_string(),
+      time_spent_seconds: self.time_spent_seconds,
+    }
+  }
+}
+
+/// A page of the worklogs on an issue
+#[derive(Deserialize)]
+struct IssueWorklogs {
+  worklogs: Vec<IssueWorklog>,
+}
+
+/// The page of issues returned by `/rest/api/3/search/jql`
+#[derive(Deserialize)]
+struct SearchResults {
+  issues: Vec<FoundIssue>,
+}
+
+#[derive(Deserialize)]
+struct FoundIssue {
+  key: String,
+  fields: FoundFields,
+}
+
+#[derive(Deserialize)]
+struct FoundFields {
+  #[serde(default)]
+  summary: String,
+  status: Option<FoundStatus>,
+}
+
+#[derive(Deserialize)]
+struct FoundStatus {
+  name: String,
+}
+
+impl From<FoundIssue> for IssueSummary {
+  fn from(issue: FoundIssue) -> IssueSummary {
+    IssueSummary {
+      key: issue.key,
+      summary: issue.fields.summary,
+      status: issue.fields.status.map(|status| status.name),
+    }
+  }
+}
+
+/// A logged in client along with the account it is logged in as
+#[derive(Clone, Debug)]
+pub struct Session {
+  pub client: JiraClient,
+  pub user: JiraUser,
+}
+
+#[derive(Clone, Debug)]
+pub struct JiraClient {
+  http: reqwest::Client,
+  /// The base url of the Jira site, without a trailing slash
+  site: Url,
+  auth: Auth,
+}
+
+impl JiraClient {
+  pub fn new(site: &str, auth: Auth) -> Result<JiraClient, JiraError> {
+    let site = site.trim().trim_end_matches('/');
+    let url = Url::parse(site).map_err(|_| JiraError::InvalidSite(site.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+      return Err(JiraError::InvalidSite(site.to_string()));
+    }
+
+    Ok(JiraClient {
+      http: reqwest::Client::new(),
+      site: url,
+      auth,
+    })
+  }
+
+  /// Log in with an API token, confirming the credentials work before handing back the client
+  pub async fn login(login: &ApiTokenLogin) -> Result<Session, JiraError> {
+    let client = JiraClient::new(
+      &login.site,
+      Auth::ApiToken {
+        email: login.email.trim().to_string(),
+        token: login.token.trim().to_string(),
+      },
+    )?;
+    let user = client.myself().await?;
+    Ok(Session { client, user })
+  }
+
+  pub fn site(&self) -> &Url {
+    &self.site
+  }
+
+  /// Fetch the account the client is authenticated as
+  pub async fn myself(&self) -> Result<JiraUser, JiraError> {
+    let request = self.request(Method::GET, "/rest/api/3/myself").await?;
+    let myself: Myself = decode(request.send().await?).await?;
+    Ok(myself.into())
+  }
+
+  /// Run a JQL search, returning up to `max_results` of the issues it finds
+  pub async fn search(&self, jql: &str, max_results: u32) -> Result<Vec<IssueSummary>, JiraError> {
+    let body = serde_json::json!({
+      "jql": jql,
+      "fields": ["summary", "status"],
+      "maxResults": max_results,
+    });
+    let request = self
+      .request(Method::POST, "/rest/api/3/search/jql")
+      .await?
+      .json(&body);
+    let results: SearchResults = decode(request.send().await?).await?;
+    Ok(results.issues.into_iter().map(IssueSummary::from).collect())
+  }
+
+  /// Record time spent on an issue, returning the worklog Jira created for it. The id is stored
+  /// with the worklog so that `find_worklog` can tell whether an attempt made it to Jira.
+  pub async fn add_worklog(&self, id: Uuid, segment: &WorkSegment) -> Result<Worklog, JiraError> {
+    let started = DateTime::<Utc>::from_timestamp_millis(segment.started as i64)
+      .ok_or_else(|| JiraError::Decode(format!("{} is not a valid start time", segment.started)))?;
+
+    let mut body = serde_json::json!({
+      "timeSpentSeconds": segment.duration,
+      // Jira is particular about the format, wanting milliseconds and an offset without a colon
+      "started": started.format("%Y-%m-%dT%H:%M:%S%.3f%z").to_string(),
+      "properties": [{ "key": WORKLOG_PROPERTY, "value": { "id": id } }],
+    });
+    if let Some(comment) = segment
+      .comment
+      .as_ref()
+      .filter(|comment| !comment.is_empty())
+    {
+      // Comments are written in the Atlassian Document Format
+      body["comment"] = serde_json::json!({
+        "type": "doc",
+        "version": 1,
+        "content": [{
+          "type": "paragraph",
+          "content": [{ "type": "text", "text": comment }],
+        }],
+      });
+    }
+
+    let path = worklog_path(&segment.issue_key)?;
+    let request = self.request(Method::POST, &path).await?.json(&body);
+    let created: IssueWorklog = decode(request.send().await?).await?;
+    Ok(created.summarize(&segment.issue_key))
+  }
+
+  /// Look for a worklog recorded by `add_worklog` under the id
+  pub async fn find_worklog(
+    &self,
+    id: Uuid,
+    segment: &WorkSegment,
+  ) -> Result<Option<Worklog>, JiraError> {
+    let path = format!(
+      "{}?startedAfter={}&expand=properties",
+      worklog_path(&segment.issue_key)?,
+      segment.started.saturating_sub(FIND_MARGIN)
+    );
+    let request = self.request(Method::GET, &path).await?;
+    let found: IssueWorklogs = decode(request.send().await?).await?;
+
+    Ok(
+      found
+        .worklogs
+        .into_iter()
+        .find(|worklog| worklog.recorded_as(id))
+        .map(|worklog| worklog.summarize(&segment.issue_key)),
+    )
+  }
+
+  /// Start a request to a path on the site with the credentials attached
+  async fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, JiraError> {
+    let url = format!("{}{}", self.site.as_str().trim_end_matches('/'), path);
+    let request = self
+      .http
+      .request(method, url)
+      .header("Accept", "application/json");
+
+    Ok(match &self.auth {
+      Auth::ApiToken { email, token } => request.basic_auth(email, Some(token)),
+      Auth::OAuth(tokens) => request.bearer_auth(tokens.access_token().await?),
+    })
+  }
+}
+
+/// The path to an issue's worklogs. The key comes from the frontend, so anything not shaped like a
+/// key is turned away rather than being allowed to change where the request goes.
+fn worklog_path(issue_key: &str) -> Result<String, JiraError> {
+  match is_issue_key(issue_key) {
+    true => Ok(format!("/rest/api/3/issue/{}/worklog", issue_key)),
+    false => Err(JiraError::InvalidIssueKey(issue_key.to_string())),
+  }
+}
+
+/// Turn any unsuccessful status into an error before decoding the body of the response
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, JiraError> {
+  let status = response.status();
+
+  // A forbidden request was made with good credentials for something they can't touch, so it is
+  // left as a status for callers to treat like any other refusal
+  if status == StatusCode::UNAUTHORIZED {
+    return Err(JiraError::Unauthorized);
+  }
+  if status == StatusCode::TOO_MANY_REQUESTS {
+    // Only the number of seconds form of the header is understood, not the http date form
+    let retry_after = response
+      .headers()
+      .get("Retry-After")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.trim().parse().ok());
+    return Err(JiraError::RateLimited { retry_after });
+  }
+  if !status.is_success() {
+    let message = response.text().await.unwrap_or_default();
+    warn!("Jira responded with {}: {}", status, message);
+    return Err(JiraError::Status {
+      code: status.as_u16(),
+      message,
+    });
+  }
+
+  Ok(response.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use wiremock::matchers::{body_json, header, method, path, query_param};
+  use wiremock::{Mock, MockServer, ResponseTemplate};
+
+  fn id() -> Uuid {
+    Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap()
+  }
+
+  fn login(server: &MockServer) -> ApiTokenLogin {
+    ApiTokenLogin {
+      site: format!("{}/", server.uri()),
+      email: "me@example.com".to_string(),
+      token: "secret-token".to_string(),
+    }
+  }
+
+  #[tokio::test]
+  async fn login_returns_the_user() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+      .and(path("/rest/api/3/myself"))
+      .and(header(
+        "authorization",
+        "Basic bWVAZXhhbXBsZS5jb206c2VjcmV0LXRva2Vu",
+      ))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "accountId": "5b10a2844c20165700ede21g",
+        "displayName": "Mia Krystof",
+        "emailAddress": "me@example.com",
+        "avatarUrls": {
+          "16x16": "https://avatar.example/16.png",
+          "48x48": "https://avatar.example/48.png",
+          "24x24": "https://avatar.example/24.png",
+        },
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+
+    let session = JiraClient::login(&login(&server)).await.unwrap();
+    assert_eq!(
+      session.user,
+      JiraUser {
+        account_id: "5b10a2844c20165700ede21g".to_string(),
+        display_name: "Mia Krystof".to_string(),
+        email_address: Some("me@example.com".to_string()),
+        avatar_url: Some("https://avatar.example/48.png".to_string()),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn login_rejects_bad_credentials() {
+    let server = MockServer::start().await;
+    Mock::given(path("/rest/api/3/myself"))
+      .respond_with(ResponseTemplate::new(401))
+      .mount(&server)
+      .await;
+
+    let result = JiraClient::login(&login(&server)).await;
+    assert_eq!(result.unwrap_err(), JiraError::Unauthorized);
+  }
+
+  #[tokio::test]
+  async fn add_worklog_posts_the_segment() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .and(path("/rest/api/3/issue/JRD-12/worklog"))
+      .and(body_json(serde_json::json!({
+        "timeSpentSeconds": 1500,
+        "started": "2024-08-20T10:00:00.000+0000",
+        "properties": [{ "key": "jiradoro", "value": { "id": "67e55044-10b1-426f-9247-bb680e5fe0c8" } }],
+        "comment": {
+          "type": "doc",
+          "version": 1,
+          "content": [{
+            "type": "paragraph",
+            "content": [{ "type": "text", "text": "Pairing on the worklogs" }],
+          }],
+        },
+      })))
+      .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+        "id": "100028",
+        "issueId": "10002",
+        "timeSpentSeconds": 1500,
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+
+    let client = JiraClient::new(
+      &server.uri(),
+      Auth::ApiToken {
+        email: "me@example.com".to_string(),
+        token: "secret-token".to_string(),
+      },
+    )
+    .unwrap();
+    let segment = WorkSegment {
+      issue_key: "JRD-12".to_string(),
+      // 2024-08-20T10:00:00Z
+      started: 1_724_148_000_000,
+      duration: 1500,
+      comment: Some("Pairing on the worklogs".to_string()),
+      segment: None,
+    };
+
+    assert_eq!(
+      client.add_worklog(id(), &segment).await.unwrap(),
+      Worklog {
+        id: "100028".to_string(),
+        issue_key: "JRD-12".to_string(),
+        time_spent_seconds: 1500,
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn add_worklog_reports_missing_issues() {
+    let server = MockServer::start().await;
+    Mock::given(path("/rest/api/3/issue/NOPE-1/worklog"))
+      .respond_with(ResponseTemplate::new(404).set_body_string("Issue does not exist"))
+      .mount(&server)
+      .await;
+
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    let client = JiraClient::new(&server.uri(), auth).unwrap();
+    let segment = WorkSegment {
+      issue_key: "NOPE-1".to_string(),
+      started: 1_724_148_000_000,
+      duration: 60,
+      comment: None,
+      segment: None,
+    };
+
+    assert_eq!(
+      client.add_worklog(id(), &segment).await.unwrap_err(),
+      JiraError::Status {
+        code: 404,
+        message: "Issue does not exist".to_string(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn worklogs_need_an_issue_key() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .respond_with(ResponseTemplate::new(201))
+      .expect(0)
+      .mount(&server)
+      .await;
+
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    let client = JiraClient::new(&server.uri(), auth).unwrap();
+    let segment = WorkSegment {
+      issue_key: "JRD-12/../../myself?".to_string(),
+      started: 1_724_148_000_000,
+      duration: 60,
+      comment: None,
+      segment: None,
+    };
+
+    assert_eq!(
+      client.add_worklog(id(), &segment).await.unwrap_err(),
+      JiraError::InvalidIssueKey(segment.issue_key.clone())
+    );
+  }
+
+  #[tokio::test]
+  async fn find_worklog_matches_the_recorded_id() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+      .and(path("/rest/api/3/issue/JRD-12/worklog"))
+      .and(query_param("startedAfter", "1724147940000"))
+      .and(query_param("expand", "properties"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "startAt": 0,
+        "total": 2,
+        "worklogs": [
+          { "id": "100027", "timeSpentSeconds": 600 },
+          {
+            "id": "100028",
+            "timeSpentSeconds": 1500,
+            "properties": [{ "key": "jiradoro", "value": { "id": id() } }],
+          },
+        ],
+      })))
+      .mount(&server)
+      .await;
+
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    let client = JiraClient::new(&server.uri(), auth).unwrap();
+    let segment = WorkSegment {
+      issue_key: "JRD-12".to_string(),
+      started: 1_724_148_000_000,
+      duration: 1500,
+      comment: None,
+      segment: None,
+    };
+
+    assert_eq!(
+      client.find_worklog(id(), &segment).await.unwrap(),
+      Some(Worklog {
+        id: "100028".to_string(),
+        issue_key: "JRD-12".to_string(),
+        time_spent_seconds: 1500,
+      })
+    );
+    assert_eq!(
+      client.find_worklog(Uuid::new_v4(), &segment).await.unwrap(),
+      None
+    );
+  }
+
+  #[tokio::test]
+  async fn search_returns_the_issues() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .and(path("/rest/api/3/search/jql"))
+      .and(body_json(serde_json::json!({
+        "jql": IssueQuery::Mine.jql(),
+        "fields": ["summary", "status"],
+        "maxResults": 20,
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "issues": [
+          {
+            "id": "10002",
+            "key": "JRD-12",
+            "fields": { "summary": "Record worklogs", "status": { "name": "In Progress" } },
+          },
+          { "id": "10003", "key": "JRD-13", "fields": { "summary": "Pick issues" } },
+        ],
+        "isLast": true,
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    let client = JiraClient::new(&server.uri(), auth).unwrap();
+    assert_eq!(
+      client.search(&IssueQuery::Mine.jql(), 20).await.unwrap(),
+      vec![
+        IssueSummary {
+          key: "JRD-12".to_string(),
+          summary: "Record worklogs".to_string(),
+          status: Some("In Progress".to_string()),
+        },
+        IssueSummary {
+          key: "JRD-13".to_string(),
+          summary: "Pick issues".to_string(),
+          status: None,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_sites_that_are_not_urls() {
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    assert!(matches!(
+      JiraClient::new("example.atlassian.net", auth),
+      Err(JiraError::InvalidSite(_))
+    ));
+  }
+}
@@ -0,0 +1,627 @@
+//! Logging in to Jira Cloud with Atlassian's OAuth 2.0 (3LO) authorization code flow.
+//!
+//! The user authorizes the app in their browser, which Atlassian then sends back to a listener on
+//! the loopback interface with a one time code. The code is exchanged for tokens using the PKCE
+//! verifier only this process knows, so no client secret has to ship with the app.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use super::{decode, Auth, JiraClient, JiraError, Session};
+
+/// Where users authorize the app and where tokens are exchanged
+pub const AUTH_URL: &str = "https://auth.atlassian.com";
+
+/// Lists the sites the tokens can reach, and proxies the Jira api for each of them
+pub const API_URL: &str = "https://api.atlassian.com";
+
+/// The permissions asked for. `offline_access` is what gets a refresh token back.
+pub const DEFAULT_SCOPES: [&str; 4] = [
+  "read:jira-user",
+  "read:jira-work",
+  "write:jira-work",
+  "offline_access",
+];
+
+/// The loopback port used when none is configured. It has to match the callback url registered
+/// with the app in the Atlassian developer console.
+pub const DEFAULT_REDIRECT_PORT: u16 = 8914;
+
+/// How long to wait for the user to finish authorizing in the browser
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Tokens are refreshed when they are this close to expiring
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// The page shown in the browser once it has been sent back to the app
+const CALLBACK_PAGE: &str = "<html><body><h1>Logged in to Jiradoro</h1>\
+  <p>You can close this tab and return to the app.</p></body></html>";
+
+/// The page shown for a redirect that doesn't belong to the login being waited on
+const MISMATCH_PAGE: &str = "<html><body><h1>Login not recognised</h1>\
+  <p>This page was not opened by the latest Jiradoro login. Start the login again from the app.</p>\
+  </body></html>";
+
+#[derive(Clone, Debug)]
+pub struct OAuthConfig {
+  pub client_id: String,
+  /// Only needed for apps that were registered as confidential clients
+  pub client_secret: Option<String>,
+  pub scopes: Vec<String>,
+  pub auth_url: Url,
+  pub api_url: Url,
+  /// The port on 127.0.0.1 the redirect is captured on. Zero picks any free port.
+  pub redirect_port: u16,
+}
+
+impl OAuthConfig {
+  pub fn new(client_id: impl Into<String>) -> OAuthConfig {
+    OAuthConfig {
+      client_id: client_id.into(),
+      client_secret: None,
+      scopes: DEFAULT_SCOPES
+        .iter()
+        .map(|scope| scope.to_string())
+        .collect(),
+      auth_url: Url::parse(AUTH_URL).unwrap(),
+      api_url: Url::parse(API_URL).unwrap(),
+      redirect_port: DEFAULT_REDIRECT_PORT,
+    }
+  }
+
+  /// Read the app registration from `JIRADORO_OAUTH_CLIENT_ID`, along with the optional
+  /// `JIRADORO_OAUTH_CLIENT_SECRET` and `JIRADORO_OAUTH_PORT`
+  pub fn from_env() -> Result<OAuthConfig, JiraError> {
+    let client_id = std::env::var("JIRADORO_OAUTH_CLIENT_ID")
+      .map_err(|_| JiraError::OAuth("JIRADORO_OAUTH_CLIENT_ID is not set".to_string()))?;
+
+    let mut config = OAuthConfig::new(client_id);
+    config.client_secret = std::env::var("JIRADORO_OAUTH_CLIENT_SECRET").ok();
+    if let Ok(port) = std::env::var("JIRADORO_OAUTH_PORT") {
+      config.redirect_port = port
+        .parse()
+        .map_err(|_| JiraError::OAuth(format!("'{}' is not a valid port", port)))?;
+    }
+    Ok(config)
+  }
+
+  /// Join a path onto one of the base urls, keeping any path the base already has
+  fn endpoint(base: &Url, path: &str) -> String {
+    format!("{}{}", base.as_str().trim_end_matches('/'), path)
+  }
+}
+
+/// The secret verifier for a login, and the challenge derived from it that is safe to share
+#[derive(Clone, Debug)]
+pub struct Pkce {
+  pub verifier: String,
+  pub challenge: String,
+}
+
+impl Pkce {
+  pub fn generate() -> Pkce {
+    let verifier = random_string(64);
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    Pkce {
+      verifier,
+      challenge,
+    }
+  }
+}
+
+fn random_string(len: usize) -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(len)
+    .map(char::from)
+    .collect()
+}
+
+/// Listens on the loopback interface for the browser to be sent back after authorizing
+pub struct Redirect {
+  listener: TcpListener,
+  uri: String,
+}
+
+impl Redirect {
+  pub async fn bind(port: u16) -> Result<Redirect, JiraError> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+      .await
+      .map_err(|err| JiraError::OAuth(format!("Could not listen for the redirect: {}", err)))?;
+    let port = listener
+      .local_addr()
+      .map_err(|err| JiraError::OAuth(err.to_string()))?
+      .port();
+
+    Ok(Redirect {
+      listener,
+      uri: format!("http://127.0.0.1:{}/callback", port),
+    })
+  }
+
+  pub fn uri(&self) -> &str {
+    &self.uri
+  }
+
+  /// Wait for the redirect and pull the authorization code out of it, making sure it belongs to
+  /// the login that was started. Anything else the browser asks for, like a favicon, is ignored, as
+  /// are redirects for other logins so they can't end this one, even with an error.
+  pub async fn code(self, state: &str) -> Result<String, JiraError> {
+    loop {
+      let (stream, _) = self
+        .listener
+        .accept()
+        .await
+        .map_err(|err| JiraError::OAuth(err.to_string()))?;
+      let (reader, mut writer) = stream.into_split();
+
+      let mut request_line = String::new();
+      BufReader::new(reader)
+        .read_line(&mut request_line)
+        .await
+        .map_err(|err| JiraError::OAuth(err.to_string()))?;
+
+      // The request line looks like "GET /callback?code=...&state=... HTTP/1.1"
+      let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+      let url = match Url::parse(&format!("http://127.0.0.1{}", target)) {
+        Ok(url) if url.path() == "/callback" => url,
+        _ => {
+          let _ = writer
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await;
+          continue;
+        }
+      };
+
+      let param = |name: &str| {
+        url
+          .query_pairs()
+          .find(|(key, _)| key == name)
+          .map(|(_, value)| value.into_owned())
+      };
+
+      let matches = param("state").as_deref() == Some(state);
+      let (status, page) = match matches {
+        true => ("200 OK", CALLBACK_PAGE),
+        false => ("400 Bad Request", MISMATCH_PAGE),
+      };
+      let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        page.len(),
+        page
+      );
+      let _ = writer.write_all(response.as_bytes()).await;
+      if !matches {
+        warn!("Ignoring a redirect that did not come from this login");
+        continue;
+      }
+
+      if let Some(error) = param("error") {
+        let description = param("error_description").unwrap_or_default();
+        return Err(JiraError::OAuth(
+          format!("{} {}", error, description).trim().to_string(),
+        ));
+      }
+      return param("code")
+        .ok_or_else(|| JiraError::OAuth("The redirect did not include a code".to_string()));
+    }
+  }
+}
+
+/// A site the tokens have been granted access to
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudSite {
+  /// The cloud id used to reach the site through the api gateway
+  pub id: String,
+  pub name: String,
+  pub url: String,
+  #[serde(default)]
+  pub scopes: Vec<String>,
+}
+
+#[derive(Default, Serialize)]
+struct TokenRequest<'a> {
+  grant_type: &'a str,
+  client_id: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  client_secret: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  code: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  redirect_uri: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  code_verifier: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  refresh_token: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+  access_token: String,
+  expires_in: u64,
+  refresh_token: Option<String>,
+}
+
+#[derive(Debug)]
+struct Tokens {
+  access_token: String,
+  refresh_token: Option<String>,
+  expires_at: Instant,
+}
+
+/// The tokens from a login, shared between every clone of the client using them
+#[derive(Clone, Debug)]
+pub struct OAuthTokens {
+  http: reqwest::Client,
+  config: Arc<OAuthConfig>,
+  tokens: Arc<Mutex<Tokens>>,
+}
+
+impl OAuthTokens {
+  /// Trade the code from the redirect for a set of tokens
+  async fn exchange(
+    http: reqwest::Client,
+    config: OAuthConfig,
+    code: &str,
+    redirect_uri: &str,
+    pkce: &Pkce,
+  ) -> Result<OAuthTokens, JiraError> {
+    let request = TokenRequest {
+      grant_type: "authorization_code",
+      client_id: &config.client_id,
+      client_secret: config.client_secret.as_deref(),
+      code: Some(code),
+      redirect_uri: Some(redirect_uri),
+      code_verifier: Some(&pkce.verifier),
+      ..TokenRequest::default()
+    };
+    let tokens = request_tokens(&http, &config, &request, None).await?;
+
+    Ok(OAuthTokens {
+      http,
+      config: Arc::new(config),
+      tokens: Arc::new(Mutex::new(tokens)),
+    })
+  }
+
+  /// A current access token, refreshing it first when it is about to expire
+  pub async fn access_token(&self) -> Result<String, JiraError> {
+    let mut tokens = self.tokens.lock().await;
+    if tokens.expires_at <= Instant::now() + REFRESH_MARGIN {
+      self.refresh(&mut tokens).await?;
+    }
+    Ok(tokens.access_token.clone())
+  }
+
+  async fn refresh(&self, tokens: &mut Tokens) -> Result<(), JiraError> {
+    let refresh_token = tokens.refresh_token.clone().ok_or_else(|| {
+      JiraError::OAuth("The login has expired and cannot be refreshed".to_string())
+    })?;
+
+    info!("Refreshing the Atlassian access token");
+    let request = TokenRequest {
+      grant_type: "refresh_token",
+      client_id: &self.config.client_id,
+      client_secret: self.config.client_secret.as_deref(),
+      refresh_token: Some(&refresh_token),
+      ..TokenRequest::default()
+    };
+    // Refresh tokens rotate, but keep the current one if a new one isn't handed out
+    *tokens = request_tokens(
+      &self.http,
+      &self.config,
+      &request,
+      Some(refresh_token.clone()),
+    )
+    .await?;
+    Ok(())
+  }
+
+  /// The sites these tokens can be used with
+  pub async fn accessible_sites(&self) -> Result<Vec<CloudSite>, JiraError> {
+    let url = OAuthConfig::endpoint(&self.config.api_url, "/oauth/token/accessible-resources");
+    let response = self
+      .http
+      .get(url)
+      .header("Accept", "application/json")
+      .bearer_auth(self.access_token().await?)
+      .send()
+      .await?;
+    decode(response).await
+  }
+}
+
+async fn request_tokens(
+  http: &reqwest::Client,
+  config: &OAuthConfig,
+  request: &TokenRequest<'_>,
+  refresh_token: Option<String>,
+) -> Result<Tokens, JiraError> {
+  let url = OAuthConfig::endpoint(&config.auth_url, "/oauth/token");
+  let response: TokenResponse = decode(http.post(url).json(request).send().await?).await?;
+
+  Ok(Tokens {
+    access_token: response.access_token,
+    refresh_token: response.refresh_token.or(refresh_token),
+    expires_at: Instant::now() + Duration::from_secs(response.expires_in),
+  })
+}
+
+/// The page the user is sent to in order to authorize the app
+pub fn authorize_url(config: &OAuthConfig, redirect_uri: &str, state: &str, pkce: &Pkce) -> Url {
+  let mut url = Url::parse(&OAuthConfig::endpoint(&config.auth_url, "/authorize")).unwrap();
+  url
+    .query_pairs_mut()
+    .append_pair("audience", "api.atlassian.com")
+    .append_pair("client_id", &config.client_id)
+    .append_pair("scope", &config.scopes.join(" "))
+    .append_pair("redirect_uri", redirect_uri)
+    .append_pair("state", state)
+    .append_pair("response_type", "code")
+    .append_pair("prompt", "consent")
+    .append_pair("code_challenge", &pkce.challenge)
+    .append_pair("code_challenge_method", "S256");
+  url
+}
+
+/// Run the whole login: send the user off to authorize the app with `open`, wait for them to come
+/// back, then log in to the first Jira site they granted access to.
+pub async fn login<F>(config: OAuthConfig, open: F) -> Result<(Session, Vec<CloudSite>), JiraError>
+where
+  F: FnOnce(&Url) -> Result<(), String>,
+{
+  let redirect = Redirect::bind(config.redirect_port).await?;
+  let redirect_uri = redirect.uri().to_string();
+  let state = random_string(32);
+  let pkce = Pkce::generate();
+
+  let url = authorize_url(&config, &redirect_uri, &state, &pkce);
+  info!("Waiting on the Atlassian login at {}", redirect_uri);
+  open(&url).map_err(|err| JiraError::OAuth(format!("Could not open the browser: {}", err)))?;
+
+  let code = tokio::time::timeout(LOGIN_TIMEOUT, redirect.code(&state))
+    .await
+    .map_err(|_| JiraError::OAuth("Timed out waiting for the login".to_string()))??;
+
+  let http = reqwest::Client::new();
+  let api_url = config.api_url.clone();
+  let tokens = OAuthTokens::exchange(http, config, &code, &redirect_uri, &pkce).await?;
+
+  let sites = tokens.accessible_sites().await?;
+  let site = sites.first().ok_or_else(|| {
+    warn!("The login was not granted access to any sites");
+    JiraError::OAuth("No Jira sites were shared with the app".to_string())
+  })?;
+
+  let client = JiraClient::new(
+    &OAuthConfig::endpoint(&api_url, &format!("/ex/jira/{}", site.id)),
+    Auth::OAuth(tokens),
+  )?;
+  let user = client.myself().await?;
+  Ok((Session { client, user }, sites))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use jiradoro_common::prelude::JiraUser;
+  use std::sync::Mutex as StdMutex;
+  use wiremock::matchers::{body_partial_json, header, method, path};
+  use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+  /// Stands in for the user approving the app: sends the browser straight back to the redirect
+  /// with a code, remembering the challenge it was given so the token exchange can check it.
+  struct Approve {
+    challenge: Arc<StdMutex<Option<String>>>,
+  }
+
+  impl Respond for Approve {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+      let param = |name: &str| {
+        request
+          .url
+          .query_pairs()
+          .find(|(key, _)| key == name)
+          .map(|(_, value)| value.into_owned())
+          .unwrap()
+      };
+      *self.challenge.lock().unwrap() = Some(param("code_challenge"));
+
+      let mut location = Url::parse(&param("redirect_uri")).unwrap();
+      location
+        .query_pairs_mut()
+        .append_pair("code", "the-code")
+        .append_pair("state", &param("state"));
+      ResponseTemplate::new(302).insert_header("Location", location.as_str())
+    }
+  }
+
+  /// Hands out tokens only when the verifier matches the challenge from the authorize step
+  struct Exchange {
+    challenge: Arc<StdMutex<Option<String>>>,
+    expires_in: u64,
+  }
+
+  impl Respond for Exchange {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+      let body: serde_json::Value = request.body_json().unwrap();
+      let verifier = body["code_verifier"].as_str().unwrap();
+      let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+      if Some(challenge) != *self.challenge.lock().unwrap() {
+        return ResponseTemplate::new(403);
+      }
+
+      ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "access_token": "access-1",
+        "refresh_token": "refresh-1",
+        "expires_in": self.expires_in,
+        "scope": DEFAULT_SCOPES.join(" "),
+      }))
+    }
+  }
+
+  /// A stand-in for both the authorization server and the api gateway
+  async fn stand_in(expires_in: u64) -> MockServer {
+    let server = MockServer::start().await;
+    let challenge = Arc::new(StdMutex::new(None));
+
+    Mock::given(method("GET"))
+      .and(path("/authorize"))
+      .respond_with(Approve {
+        challenge: challenge.clone(),
+      })
+      .mount(&server)
+      .await;
+    Mock::given(method("POST"))
+      .and(path("/oauth/token"))
+      .and(body_partial_json(serde_json::json!({
+        "grant_type": "authorization_code",
+        "client_id": "the-client",
+        "code": "the-code",
+      })))
+      .respond_with(Exchange {
+        challenge,
+        expires_in,
+      })
+      .expect(1)
+      .mount(&server)
+      .await;
+    server
+  }
+
+  async fn mount_sites(server: &MockServer, token: &str) {
+    Mock::given(method("GET"))
+      .and(path("/oauth/token/accessible-resources"))
+      .and(header("authorization", format!("Bearer {}", token)))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+        "id": "cloud-1",
+        "name": "example",
+        "url": "https://example.atlassian.net",
+        "scopes": ["read:jira-user"],
+        "avatarUrl": "https://site-admin-avatar-cdn.prod.public.atl-paas.net/avatars/240/rocket.png",
+      }])))
+      .mount(server)
+      .await;
+  }
+
+  async fn mount_myself(server: &MockServer, token: &str) {
+    Mock::given(method("GET"))
+      .and(path("/ex/jira/cloud-1/rest/api/3/myself"))
+      .and(header("authorization", format!("Bearer {}", token)))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "accountId": "5b10a2844c20165700ede21g",
+        "displayName": "Mia Krystof",
+      })))
+      .mount(server)
+      .await;
+  }
+
+  fn config(server: &MockServer) -> OAuthConfig {
+    OAuthConfig {
+      auth_url: Url::parse(&server.uri()).unwrap(),
+      api_url: Url::parse(&server.uri()).unwrap(),
+      redirect_port: 0,
+      ..OAuthConfig::new("the-client")
+    }
+  }
+
+  /// Plays the part of the browser, following the authorize page back to the app
+  fn browser(url: &Url) -> Result<(), String> {
+    let url = url.clone();
+    tokio::spawn(async move { reqwest::get(url).await.unwrap() });
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn login_end_to_end() {
+    let server = stand_in(3600).await;
+    mount_sites(&server, "access-1").await;
+    mount_myself(&server, "access-1").await;
+
+    let (session, sites) = login(config(&server), browser).await.unwrap();
+    assert_eq!(
+      session.user,
+      JiraUser {
+        account_id: "5b10a2844c20165700ede21g".to_string(),
+        display_name: "Mia Krystof".to_string(),
+        email_address: None,
+        avatar_url: None,
+      }
+    );
+    assert_eq!(sites.len(), 1);
+    assert_eq!(sites[0].id, "cloud-1");
+    assert!(session.client.site().as_str().ends_with("/ex/jira/cloud-1"));
+  }
+
+  #[tokio::test]
+  async fn refreshes_expired_tokens() {
+    let server = stand_in(0).await;
+    Mock::given(method("POST"))
+      .and(path("/oauth/token"))
+      .and(body_partial_json(serde_json::json!({
+        "grant_type": "refresh_token",
+        "client_id": "the-client",
+        "refresh_token": "refresh-1",
+      })))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "access_token": "access-2",
+        "expires_in": 3600,
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+    mount_sites(&server, "access-2").await;
+    mount_myself(&server, "access-2").await;
+
+    let (session, _) = login(config(&server), browser).await.unwrap();
+    assert_eq!(session.user.display_name, "Mia Krystof");
+
+    // The refreshed token is still good, so it is used as is from here on
+    session.client.myself().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn ignores_redirects_for_other_logins() {
+    let redirect = Redirect::bind(0).await.unwrap();
+    let uri = redirect.uri().to_string();
+    let browser = tokio::spawn(async move {
+      let mut statuses = Vec::new();
+      for query in [
+        "code=stolen-code&state=someone-else",
+        "error=access_denied",
+        "code=the-code&state=mine",
+      ] {
+        let response = reqwest::get(format!("{}?{}", uri, query)).await.unwrap();
+        statuses.push(response.status().as_u16());
+      }
+      statuses
+    });
+
+    assert_eq!(redirect.code("mine").await, Ok("the-code".to_string()));
+    assert_eq!(browser.await.unwrap(), vec![400, 400, 200]);
+  }
+
+  #[tokio::test]
+  async fn reports_a_denied_login() {
+    let redirect = Redirect::bind(0).await.unwrap();
+    let url = format!("{}?error=access_denied&state=mine", redirect.uri());
+    tokio::spawn(async move { reqwest::get(url).await.unwrap() });
+
+    assert_eq!(
+      redirect.code("mine").await,
+      Err(JiraError::OAuth("access_denied".to_string()))
+    );
+  }
+}
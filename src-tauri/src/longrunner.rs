@@ -2,21 +2,177 @@
 //! layer. It is meant to consolidate and simplify the communication pattern for the frontend
 //! speaking to the server and vice versa.
 
-use std::collections::HashMap;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use jiradoro_common::prelude::{Payload, ProcessStatus};
+
+/// The number of processes allowed to run at the same time when no explicit size is given
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// How many unread messages a channel in or out of a process can hold before the sender waits
+const CHANNEL_SIZE: usize = 32;
+
+/// How many completed processes are kept so their status and log can still be looked up. The
+/// oldest are dropped first, as otherwise every process ever run would stay in memory.
+const FINISHED_LIMIT: usize = 64;
+
+/// A callback used to hand emissions from a process to whoever is listening outside the runner
+pub type Emitter = Box<dyn Fn(Uuid, Payload) + Send + Sync>;
+
+/// The handles given to a running process for talking with the rest of the application.
 pub struct ProcessTools {
-  // The receiver for incoming messages
-  // A sender to emit messages to the longrunner
-  // A pointer to the log
+  /// The id of the process these tools belong to
+  pub guid: Uuid,
+  /// The receiver for incoming messages
+  pub requests: mpsc::Receiver<ProcessRequest>,
+  /// A sender to emit messages to the longrunner
+  pub emissions: mpsc::Sender<ProcessEmission>,
+  /// A pointer to the log
+  pub log: Arc<Mutex<Vec<String>>>,
+  /// Triggered when the process has been asked to stop
+  pub cancellation_token: CancellationToken,
+}
+
+impl ProcessTools {
+  /// Send a payload out to the frontend
+  pub async fn emit(&self, payload: Payload) {
+    if self
+      .emissions
+      .send(ProcessEmission::Emit(payload))
+      .await
+      .is_err()
+    {
+      warn!(
+        "Process {} emitted after its listener was closed",
+        self.guid
+      );
+    }
+  }
+
+  /// Append a line to the process log
+  pub async fn log(&self, line: impl Into<String>) {
+    self.log.lock().await.push(line.into());
+  }
+
+  /// Check whether the process has been asked to stop
+  pub fn is_cancelled(&self) -> bool {
+    self.cancellation_token.is_cancelled()
+  }
+
+  /// Check for a pause request without waiting. Pausable processes should call this between units
+  /// of work and return a checkpoint once it is true.
+  pub fn pause_requested(&mut self) -> bool {
+    let mut requested = false;
+    while let Ok(request) = self.requests.try_recv() {
+      match request {
+        ProcessRequest::Pause => requested = true,
+      }
+    }
+    requested
+  }
+}
+
+/// A unit of background work that can be handed to the LongRunner. The implementing struct is the
+/// input of the process, and it is consumed when the process is started.
+#[async_trait]
+pub trait LongRunnerProcess: Send + Sync + 'static {
+  /// The value returned when the process completes successfully. It is serialized to JSON and
+  /// stored in `Status::Finished`.
+  type Output: Serialize + Send;
+  /// The reason the process could not complete, stored in `Status::Errored`
+  type Error: Display + Send;
+
+  /// Do the work of the process
+  async fn start(self, tools: ProcessTools) -> Result<Self::Output, Self::Error>;
+
+  /// How long the process is given to wind down after its cancellation token is triggered before
+  /// it is killed. Processes that never check the token should leave this as None, which makes a
+  /// cancel the same as a kill.
+  fn grace_period(&self) -> Option<Duration> {
+    None
+  }
+}
+
+/// How a pausable process stopped running
+#[derive(PartialEq, Clone, Debug)]
+pub enum Progress<T, C> {
+  /// The work was completed with the final output
+  Done(T),
+  /// The process stopped early after being asked to pause, leaving a checkpoint to resume from
+  Paused(C),
 }
 
-pub trait LongRunnerProcess {
-  fn start(proc: Arc<Mutex<ProcessTools>>);
+/// A process that can be stopped part way through and picked back up later. When it receives a
+/// `ProcessRequest::Pause` it should return `Progress::Paused` with enough state to carry on from
+/// where it left off.
+#[async_trait]
+pub trait PausableProcess: Send + Sync + Sized + 'static {
+  /// The value returned when the process completes successfully
+  type Output: Serialize + Send;
+  /// The reason the process could not complete
+  type Error: Display + Send;
+  /// The state handed back when paused. It is serialized while the process is waiting to resume.
+  type Checkpoint: Serialize + DeserializeOwned + Send;
+
+  /// Do the work of the process until it is either done or asked to pause
+  async fn start(
+    self,
+    tools: ProcessTools,
+  ) -> Result<Progress<Self::Output, Self::Checkpoint>, Self::Error>;
+
+  /// Rebuild the process from the checkpoint it handed back when it was paused
+  fn resume(checkpoint: Self::Checkpoint) -> Self;
+
+  /// How long the process is given to wind down after being cancelled before it is killed
+  fn grace_period(&self) -> Option<Duration> {
+    None
+  }
+}
+
+/// The type erased version of a process that the workers actually run. It resolves to a
+/// serialized result or checkpoint on success, or a description of the failure.
+pub type Task = Box<
+  dyn FnOnce(ProcessTools) -> BoxFuture<'static, Result<Progress<String, String>, String>>
+    + Send
+    + Sync,
+>;
+
+/// Rebuilds the task of a paused process from its serialized checkpoint
+type Resume = fn(&str) -> Result<Task, String>;
+
+fn pausable_task<P: PausableProcess>(process: P) -> Task {
+  Box::new(move |tools| {
+    Box::pin(async move {
+      match process.start(tools).await {
+        Ok(Progress::Done(output)) => serde_json::to_string(&output)
+          .map(Progress::Done)
+          .map_err(|err| err.to_string()),
+        Ok(Progress::Paused(checkpoint)) => serde_json::to_string(&checkpoint)
+          .map(Progress::Paused)
+          .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+      }
+    })
+  })
+}
+
+fn resume_task<P: PausableProcess>(checkpoint: &str) -> Result<Task, String> {
+  let checkpoint = serde_json::from_str(checkpoint).map_err(|err| err.to_string())?;
+  Ok(pausable_task(P::resume(checkpoint)))
 }
 
+#[derive(PartialEq, Clone, Debug)]
 pub enum Action {
   /// Add a new process to the end of the run line
   Queue,
@@ -34,6 +190,7 @@ pub enum Action {
   Status,
 }
 
+#[derive(PartialEq, Clone, Debug)]
 pub enum Status {
   /// Waiting to be run.
   Queued,
@@ -46,18 +203,55 @@ pub enum Status {
   /// The process was ended before it had a chance to complete
   Stopped,
   /// The process failed with an error
-  Errored,
+  Errored(String),
+}
+
+impl From<Status> for ProcessStatus {
+  fn from(status: Status) -> ProcessStatus {
+    match status {
+      Status::Queued => ProcessStatus::Queued,
+      Status::Running => ProcessStatus::Running,
+      Status::Finished(value) => ProcessStatus::Finished(value),
+      Status::Paused => ProcessStatus::Paused,
+      Status::Stopped => ProcessStatus::Stopped,
+      Status::Errored(err) => ProcessStatus::Errored(err),
+    }
+  }
+}
+
+/// The reasons an action could not be applied to a process
+#[derive(PartialEq, Clone, Debug)]
+pub enum LongRunnerError {
+  /// No process with the id is known to the runner
+  NotFound(Uuid),
+  /// The process has already completed and can no longer be changed
+  Completed(Uuid),
+  /// The process is running and does not know how to pause
+  NotPausable(Uuid),
+}
+
+impl Display for LongRunnerError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LongRunnerError::NotFound(guid) => write!(f, "No process was found with id {}", guid),
+      LongRunnerError::Completed(guid) => write!(f, "Process {} has already completed", guid),
+      LongRunnerError::NotPausable(guid) => write!(f, "Process {} cannot be paused", guid),
+    }
+  }
 }
 
 /// Commands that can be sent into a running process via channel
-pub enum ProcessRequest {}
+#[derive(PartialEq, Clone, Debug)]
+pub enum ProcessRequest {
+  /// Stop at the next convenient point and return a checkpoint
+  Pause,
+}
 
 /// Messages that can be returned from within a running process.
 pub enum ProcessEmission {
-  /// Trigger sending an event with the string as the payload. The payload should be pre-serialized
-  /// as nothing cares about its contents outside of the frontend listener
-  Emit(String),
-  /// Add the vector of strings to the log
+  /// Trigger sending an event with the payload to the frontend listener
+  Emit(Payload),
+  /// Add the vector of strings to the log, forwarding each line to the frontend listener
   Log(Vec<String>),
 }
 
@@ -67,34 +261,897 @@ pub struct Process {
   guid: Uuid,
   /// The current state of the process
   status: Status,
+  /// The work to be done, taken by the worker when the process is started
+  task: Option<Task>,
+  /// The worker running the task, available while the process is in the running pool
+  handle: Option<JoinHandle<()>>,
   /// The channel to send messages into the running process
   input: Option<mpsc::Sender<ProcessRequest>>,
   /// A channel to listen for emissions from the running process
   listener: Option<mpsc::Receiver<ProcessEmission>>,
   /// A collection of log messages that may be retrieved by the frontend when desired
-  log: Vec<String>,
-  /// A token that is used to kill the running process
-  cancellation_token: String,
+  log: Arc<Mutex<Vec<String>>>,
+  /// A token that is used to ask the running process to stop
+  cancellation_token: CancellationToken,
+  /// How long to wait after a cancellation before killing the process
+  grace_period: Option<Duration>,
+  /// Rebuilds the process from a checkpoint, only available for processes that can be paused
+  resume: Option<Resume>,
+  /// The serialized state the process handed back when it was last paused
+  checkpoint: Option<String>,
+}
+
+impl Process {
+  pub fn new<P: LongRunnerProcess>(process: P) -> Process {
+    let grace_period = process.grace_period();
+    let task: Task = Box::new(move |tools| {
+      Box::pin(async move {
+        match process.start(tools).await {
+          Ok(output) => serde_json::to_string(&output)
+            .map(Progress::Done)
+            .map_err(|err| err.to_string()),
+          Err(err) => Err(err.to_string()),
+        }
+      })
+    });
+    Process::from_task(task, grace_period, None)
+  }
+
+  /// Wrap a process that can be paused and later resumed from a checkpoint
+  pub fn pausable<P: PausableProcess>(process: P) -> Process {
+    let grace_period = process.grace_period();
+    Process::from_task(pausable_task(process), grace_period, Some(resume_task::<P>))
+  }
+
+  fn from_task(task: Task, grace_period: Option<Duration>, resume: Option<Resume>) -> Process {
+    Process {
+      guid: Uuid::new_v4(),
+      status: Status::Queued,
+      task: Some(task),
+      handle: None,
+      input: None,
+      listener: None,
+      log: Arc::new(Mutex::new(Vec::new())),
+      cancellation_token: CancellationToken::new(),
+      grace_period,
+      resume,
+      checkpoint: None,
+    }
+  }
+
+  pub fn guid(&self) -> Uuid {
+    self.guid
+  }
+
+  pub fn status(&self) -> &Status {
+    &self.status
+  }
+
+  /// Create the channels for the process and hand back the ends the process itself will use
+  fn tools(&mut self) -> ProcessTools {
+    let (input, requests) = mpsc::channel(CHANNEL_SIZE);
+    let (emissions, listener) = mpsc::channel(CHANNEL_SIZE);
+    self.input = Some(input);
+    self.listener = Some(listener);
+
+    ProcessTools {
+      guid: self.guid,
+      requests,
+      emissions,
+      log: self.log.clone(),
+      cancellation_token: self.cancellation_token.clone(),
+    }
+  }
 }
 
 pub struct LongRunner {
+  /// The maximum number of processes that may be in the running pool at once
+  workers: usize,
   /// The ordered set of processes waiting to be run
-  queue: Mutex<Vec<Process>>,
+  queue: Mutex<VecDeque<Process>>,
   /// The pool of processes that have been started
   running: Mutex<HashMap<Uuid, Process>>,
   /// The pool of processes that have been paused
   paused: Mutex<HashMap<Uuid, Process>>,
-  /// The pool of processes that have been completed, both success and failures.
-  finished: Mutex<HashMap<Uuid, Process>>,
+  /// The processes that have been completed, both success and failures, oldest first
+  finished: Mutex<VecDeque<Process>>,
+  /// Where emissions from running processes are sent
+  emitter: Option<Emitter>,
+}
+
+impl Default for LongRunner {
+  fn default() -> LongRunner {
+    LongRunner::new(DEFAULT_WORKERS)
+  }
 }
 
+// The pools are always locked in the order queue -> running -> paused -> finished so that two
+// tasks moving processes in opposite directions can never deadlock each other. A process being
+// moved keeps the pool it leaves locked until it is in the next one, so it can always be found.
 impl LongRunner {
-  pub fn queue(&self) {}
+  pub fn new(workers: usize) -> LongRunner {
+    LongRunner {
+      workers: workers.max(1),
+      queue: Mutex::new(VecDeque::new()),
+      running: Mutex::new(HashMap::new()),
+      paused: Mutex::new(HashMap::new()),
+      finished: Mutex::new(VecDeque::new()),
+      emitter: None,
+    }
+  }
+
+  /// Forward emissions from all processes to the given callback
+  pub fn with_emitter(mut self, emitter: impl Fn(Uuid, Payload) + Send + Sync + 'static) -> Self {
+    self.emitter = Some(Box::new(emitter));
+    self
+  }
+
+  /// Add a new process to the end of the run line, starting it immediately if a worker is free.
+  pub async fn queue(self: &Arc<Self>, mut process: Process) -> Uuid {
+    let guid = process.guid;
+    process.status = Status::Queued;
+    info!("Queueing process {}", guid);
+
+    self.queue.lock().await.push_back(process);
+    self.schedule().await;
+    guid
+  }
+
+  /// Retrieve the current status of a process, if the LongRunner knows about it. Every pool is held
+  /// at once, so a process on its way from one to the next is still found.
+  pub async fn status(&self, guid: &Uuid) -> Option<Status> {
+    let queue = self.queue.lock().await;
+    let running = self.running.lock().await;
+    let paused = self.paused.lock().await;
+    let finished = self.finished.lock().await;
+    queue
+      .iter()
+      .find(|p| p.guid == *guid)
+      .or_else(|| running.get(guid))
+      .or_else(|| paused.get(guid))
+      .or_else(|| finished.iter().find(|p| p.guid == *guid))
+      .map(|proc| proc.status.clone())
+  }
+
+  /// Retrieve the log of a completed process
+  pub async fn log(&self, guid: &Uuid) -> Option<Vec<String>> {
+    let log = self
+      .finished
+      .lock()
+      .await
+      .iter()
+      .find(|p| p.guid == *guid)
+      .map(|proc| proc.log.clone())?;
+    let lines = log.lock().await.clone();
+    Some(lines)
+  }
+
+  /// Start a process on the next available worker. Paused processes are rebuilt from their
+  /// checkpoint and queued processes jump to the front of the line.
+  pub async fn run(self: &Arc<Self>, guid: Uuid) -> Result<Status, LongRunnerError> {
+    {
+      let mut queue = self.queue.lock().await;
+      if let Some(index) = queue.iter().position(|p| p.guid == guid) {
+        if let Some(process) = queue.remove(index) {
+          queue.push_front(process);
+        }
+      } else if self.running.lock().await.contains_key(&guid) {
+        return Ok(Status::Running);
+      } else {
+        let mut paused = self.paused.lock().await;
+        let mut process = match paused.remove(&guid) {
+          Some(process) => process,
+          None => return Err(self.missing(guid).await),
+        };
+
+        if process.task.is_none() {
+          let rebuilt = match (process.resume, process.checkpoint.take()) {
+            (Some(resume), Some(checkpoint)) => resume(&checkpoint),
+            _ => Err("Paused process has no checkpoint to resume from".to_string()),
+          };
+          match rebuilt {
+            Ok(task) => process.task = Some(task),
+            Err(err) => {
+              warn!("Could not resume process {}: {}", guid, err);
+              process.status = Status::Errored(err);
+              let status = process.status.clone();
+              self.retire(process, &mut *self.finished.lock().await);
+              return Ok(status);
+            }
+          }
+        }
+        drop(paused);
+
+        info!("Resuming process {}", guid);
+        process.status = Status::Queued;
+        queue.push_front(process);
+      }
+    }
+
+    self.schedule().await;
+    Ok(self.status(&guid).await.unwrap_or(Status::Queued))
+  }
+
+  /// Pause a process. Queued processes are pulled out of the line to be run later, while running
+  /// processes are asked to stop and hand back a checkpoint.
+  pub async fn pause(self: &Arc<Self>, guid: Uuid) -> Result<Status, LongRunnerError> {
+    let mut queue = self.queue.lock().await;
+    if let Some(index) = queue.iter().position(|p| p.guid == guid) {
+      if let Some(mut process) = queue.remove(index) {
+        info!("Pulling process {} out of the queue", guid);
+        process.status = Status::Paused;
+        self.paused.lock().await.insert(guid, process);
+      }
+      return Ok(Status::Paused);
+    }
+    drop(queue);
+
+    let running = self.running.lock().await;
+    match running.get(&guid) {
+      Some(process) => match (&process.resume, &process.input) {
+        (Some(_), Some(input)) => {
+          info!("Asking process {} to pause", guid);
+          if input.try_send(ProcessRequest::Pause).is_err() {
+            warn!("Process {} is not listening for requests", guid);
+          }
+          Ok(Status::Running)
+        }
+        _ => Err(LongRunnerError::NotPausable(guid)),
+      },
+      None => {
+        drop(running);
+        match self.paused.lock().await.contains_key(&guid) {
+          true => Ok(Status::Paused),
+          false => Err(self.missing(guid).await),
+        }
+      }
+    }
+  }
+
+  /// Gracefully stop a process. Processes that have not started are removed from the line, while
+  /// running processes have their cancellation token triggered and are killed if they are still
+  /// running once their grace period runs out.
+  pub async fn cancel(self: &Arc<Self>, guid: Uuid) -> Result<Status, LongRunnerError> {
+    if let Some(status) = self.remove_waiting(guid).await {
+      return Ok(status);
+    }
+
+    let grace_period = {
+      let running = self.running.lock().await;
+      match running.get(&guid) {
+        Some(process) => {
+          process.cancellation_token.cancel();
+          process.grace_period
+        }
+        None => return Err(self.missing(guid).await),
+      }
+    };
+
+    match grace_period {
+      Some(grace_period) => {
+        info!(
+          "Cancelling process {} with {:?} to exit",
+          guid, grace_period
+        );
+        let runner = self.clone();
+        tokio::spawn(async move {
+          tokio::time::sleep(grace_period).await;
+          if runner.running.lock().await.contains_key(&guid) {
+            warn!("Process {} ignored its cancellation, killing it", guid);
+            let _ = runner.kill(guid).await;
+          }
+        });
+        Ok(Status::Running)
+      }
+      None => self.kill(guid).await,
+    }
+  }
+
+  /// Immediately stop a process, aborting its worker if it is running
+  pub async fn kill(self: &Arc<Self>, guid: Uuid) -> Result<Status, LongRunnerError> {
+    if let Some(status) = self.remove_waiting(guid).await {
+      return Ok(status);
+    }
+
+    let mut running = self.running.lock().await;
+    let mut process = match running.remove(&guid) {
+      Some(process) => process,
+      None => {
+        drop(running);
+        return Err(self.missing(guid).await);
+      }
+    };
+
+    info!("Killing process {}", guid);
+    process.cancellation_token.cancel();
+    if let Some(handle) = process.handle.take() {
+      handle.abort();
+    }
+    process.status = Status::Stopped;
+    self.retire(process, &mut *self.finished.lock().await);
+    drop(running);
+
+    self.schedule().await;
+    Ok(Status::Stopped)
+  }
+
+  /// Stop a process that has not been started yet, moving it straight to the finished pool
+  async fn remove_waiting(&self, guid: Uuid) -> Option<Status> {
+    let mut queue = self.queue.lock().await;
+    let mut paused = self.paused.lock().await;
+    let mut process = match queue.iter().position(|p| p.guid == guid) {
+      Some(index) => queue.remove(index),
+      None => paused.remove(&guid),
+    }?;
+
+    info!("Removing process {} before it could run", guid);
+    process.status = Status::Stopped;
+    self.retire(process, &mut *self.finished.lock().await);
+    Some(Status::Stopped)
+  }
+
+  /// Move a process that will not run again into the finished pool, letting the listener know how
+  /// it ended. The pool is passed in locked, so the process can be moved without letting go of the
+  /// pool it came from.
+  fn retire(&self, process: Process, finished: &mut VecDeque<Process>) {
+    let payload = match &process.status {
+      Status::Finished(value) => Some(Payload::Result(value.clone())),
+      Status::Errored(err) => Some(Payload::Error(err.clone())),
+      Status::Stopped => Some(Payload::Stopped),
+      _ => None,
+    };
+    if let (Some(emitter), Some(payload)) = (&self.emitter, payload) {
+      emitter(process.guid, payload);
+    }
+
+    finished.push_back(process);
+    if finished.len() > FINISHED_LIMIT {
+      finished.pop_front();
+    }
+  }
+
+  /// The error to report when an action is applied to a process that is not active
+  async fn missing(&self, guid: Uuid) -> LongRunnerError {
+    match self.finished.lock().await.iter().any(|p| p.guid == guid) {
+      true => LongRunnerError::Completed(guid),
+      false => LongRunnerError::NotFound(guid),
+    }
+  }
+
+  /// Promote queued processes into the running pool until every worker is busy
+  async fn schedule(self: &Arc<Self>) {
+    let mut queue = self.queue.lock().await;
+    let mut running = self.running.lock().await;
+
+    while running.len() < self.workers {
+      let mut process = match queue.pop_front() {
+        Some(process) => process,
+        None => break,
+      };
+
+      let guid = process.guid;
+      let task = match process.task.take() {
+        Some(task) => task,
+        None => {
+          warn!("Process {} was queued without a task to run", guid);
+          continue;
+        }
+      };
+
+      info!("Starting process {}", guid);
+      process.status = Status::Running;
+      let tools = process.tools();
+      if let Some(listener) = process.listener.take() {
+        self.listen(guid, listener, process.log.clone());
+      }
+
+      let runner = self.clone();
+      process.handle = Some(tokio::spawn(async move {
+        let result = task(tools).await;
+        runner.complete(guid, result).await;
+      }));
+      running.insert(guid, process);
+    }
+  }
+
+  /// Drain the emissions of a running process until all of its senders have been dropped
+  fn listen(
+    self: &Arc<Self>,
+    guid: Uuid,
+    mut listener: mpsc::Receiver<ProcessEmission>,
+    log: Arc<Mutex<Vec<String>>>,
+  ) {
+    let runner = self.clone();
+    tokio::spawn(async move {
+      while let Some(emission) = listener.recv().await {
+        match emission {
+          ProcessEmission::Emit(payload) => match &runner.emitter {
+            Some(emitter) => emitter(guid, payload),
+            None => info!("Process {} emitted with no listener: {:?}", guid, payload),
+          },
+          ProcessEmission::Log(mut lines) => {
+            if let Some(emitter) = &runner.emitter {
+              for line in lines.iter() {
+                emitter(guid, Payload::Log(line.clone()));
+              }
+            }
+            log.lock().await.append(&mut lines);
+          }
+        }
+      }
+    });
+  }
+
+  /// Move a process out of the running pool once its worker has returned and free the worker up
+  /// for the next process in line. This is boxed as it is called from inside the spawned worker,
+  /// which would otherwise make the worker's future recursively defined.
+  fn complete(
+    self: Arc<Self>,
+    guid: Uuid,
+    result: Result<Progress<String, String>, String>,
+  ) -> BoxFuture<'static, ()> {
+    Box::pin(async move {
+      let mut running = self.running.lock().await;
+      let mut process = match running.remove(&guid) {
+        Some(process) => process,
+        None => {
+          warn!("Process {} completed but was not in the running pool", guid);
+          drop(running);
+          self.schedule().await;
+          return;
+        }
+      };
+      process.handle = None;
+      process.input = None;
+
+      match result {
+        _ if process.cancellation_token.is_cancelled() => {
+          info!("Process {} stopped after being cancelled", guid);
+          process.status = Status::Stopped;
+          self.retire(process, &mut *self.finished.lock().await);
+        }
+        Ok(Progress::Paused(checkpoint)) => {
+          info!("Process {} paused", guid);
+          process.status = Status::Paused;
+          process.checkpoint = Some(checkpoint);
+          self.paused.lock().await.insert(guid, process);
+        }
+        Ok(Progress::Done(value)) => {
+          info!("Process {} finished", guid);
+          process.status = Status::Finished(value);
+          self.retire(process, &mut *self.finished.lock().await);
+        }
+        Err(err) => {
+          warn!("Process {} errored: {}", guid, err);
+          process.status = Status::Errored(err);
+          self.retire(process, &mut *self.finished.lock().await);
+        }
+      }
+      drop(running);
+
+      self.schedule().await;
+    })
+  }
 }
 
 pub mod prelude {
   pub use super::{
-    Action, LongRunner, LongRunnerProcess, Process, ProcessEmission, ProcessRequest, ProcessTools,
-    Status,
+    Action, LongRunner, LongRunnerError, LongRunnerProcess, PausableProcess, Process,
+    ProcessEmission, ProcessRequest, ProcessTools, Progress, Status,
   };
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use tokio::sync::Notify;
+
+  /// Waits to be let through, emitting a line once it is, before ending with the given outcome
+  struct Gate {
+    open: Arc<Notify>,
+    outcome: Result<&'static str, &'static str>,
+  }
+
+  impl Gate {
+    fn new(outcome: Result<&'static str, &'static str>) -> (Gate, Arc<Notify>) {
+      let open = Arc::new(Notify::new());
+      (
+        Gate {
+          open: open.clone(),
+          outcome,
+        },
+        open,
+      )
+    }
+  }
+
+  #[async_trait]
+  impl LongRunnerProcess for Gate {
+    type Output = &'static str;
+    type Error = &'static str;
+
+    async fn start(self, tools: ProcessTools) -> Result<&'static str, &'static str> {
+      self.open.notified().await;
+      tools.emit(Payload::Log("opened".to_string())).await;
+      self.outcome
+    }
+  }
+
+  /// Marks that a process's work was dropped, whether it returned or was aborted
+  struct Dropped(Arc<AtomicBool>);
+
+  impl Drop for Dropped {
+    fn drop(&mut self) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  /// Runs until it is cancelled, if it listens for that at all
+  struct Stubborn {
+    listens: bool,
+    grace_period: Option<Duration>,
+    ended: Arc<AtomicBool>,
+  }
+
+  #[async_trait]
+  impl LongRunnerProcess for Stubborn {
+    type Output = ();
+    type Error = String;
+
+    async fn start(self, tools: ProcessTools) -> Result<(), String> {
+      let _dropped = Dropped(self.ended.clone());
+      match self.listens {
+        true => tools.cancellation_token.cancelled().await,
+        false => futures::future::pending().await,
+      }
+      Ok(())
+    }
+
+    fn grace_period(&self) -> Option<Duration> {
+      self.grace_period
+    }
+  }
+
+  /// Counts up to a number a step at a time, keeping where it got to when paused
+  struct Count {
+    from: u32,
+    to: u32,
+  }
+
+  #[async_trait]
+  impl PausableProcess for Count {
+    /// Where the last run picked up from, and where it finished
+    type Output = (u32, u32);
+    type Error = String;
+    type Checkpoint = (u32, u32);
+
+    async fn start(
+      mut self,
+      mut tools: ProcessTools,
+    ) -> Result<Progress<(u32, u32), (u32, u32)>, String> {
+      let from = self.from;
+      while self.from < self.to {
+        if tools.pause_requested() {
+          return Ok(Progress::Paused((self.from, self.to)));
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        self.from += 1;
+      }
+      Ok(Progress::Done((from, self.to)))
+    }
+
+    fn resume((from, to): (u32, u32)) -> Count {
+      Count { from, to }
+    }
+  }
+
+  /// Wait for the work of an aborted process to be dropped, which happens once its worker is next
+  /// polled rather than as it is aborted
+  async fn aborted(ended: &AtomicBool) -> bool {
+    for _ in 0..100 {
+      if ended.load(Ordering::SeqCst) {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    ended.load(Ordering::SeqCst)
+  }
+
+  impl LongRunner {
+    /// Wait for the process to reach the status, failing if it takes too long to get there
+    async fn settle(&self, guid: Uuid, status: Status) {
+      for _ in 0..200 {
+        if self.status(&guid).await.as_ref() == Some(&status) {
+          return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+      assert_eq!(self.status(&guid).await, Some(status));
+    }
+  }
+
+  #[tokio::test]
+  async fn processes_wait_for_a_free_worker() {
+    let runner = Arc::new(LongRunner::new(2));
+    let (first, open_first) = Gate::new(Ok("first"));
+    let (second, _) = Gate::new(Ok("second"));
+    let (third, open_third) = Gate::new(Ok("third"));
+    let first = runner.queue(Process::new(first)).await;
+    let second = runner.queue(Process::new(second)).await;
+    let third = runner.queue(Process::new(third)).await;
+
+    assert_eq!(runner.status(&first).await, Some(Status::Running));
+    assert_eq!(runner.status(&second).await, Some(Status::Running));
+    assert_eq!(runner.status(&third).await, Some(Status::Queued));
+
+    // Opening the last in line does nothing until a worker is free for it
+    open_third.notify_one();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(runner.status(&third).await, Some(Status::Queued));
+
+    open_first.notify_one();
+    runner
+      .settle(first, Status::Finished("\"first\"".to_string()))
+      .await;
+    runner
+      .settle(third, Status::Finished("\"third\"".to_string()))
+      .await;
+    assert_eq!(runner.status(&second).await, Some(Status::Running));
+    assert_eq!(runner.status(&Uuid::new_v4()).await, None);
+  }
+
+  #[tokio::test]
+  async fn completed_processes_keep_their_outcome() {
+    let runner = Arc::new(LongRunner::new(1));
+    let (failing, open) = Gate::new(Err("no luck"));
+    let failing = runner.queue(Process::new(failing)).await;
+    let (waiting, _) = Gate::new(Ok("waiting"));
+    let waiting = runner.queue(Process::new(waiting)).await;
+
+    // Running a queued process moves it to the front of the line without starting it early
+    assert_eq!(runner.run(waiting).await, Ok(Status::Queued));
+    open.notify_one();
+    runner
+      .settle(failing, Status::Errored("no luck".to_string()))
+      .await;
+    runner.settle(waiting, Status::Running).await;
+
+    assert_eq!(
+      runner.run(failing).await,
+      Err(LongRunnerError::Completed(failing))
+    );
+    assert_eq!(
+      runner.kill(failing).await,
+      Err(LongRunnerError::Completed(failing))
+    );
+    let unknown = Uuid::new_v4();
+    assert_eq!(
+      runner.cancel(unknown).await,
+      Err(LongRunnerError::NotFound(unknown))
+    );
+  }
+
+  #[tokio::test]
+  async fn paused_processes_resume_from_their_checkpoint() {
+    let runner = Arc::new(LongRunner::new(1));
+    let guid = runner
+      .queue(Process::pausable(Count { from: 0, to: 20 }))
+      .await;
+    tokio::time::sleep(Duration::from_millis(30)).await;
+
+    assert_eq!(runner.pause(guid).await, Ok(Status::Running));
+    runner.settle(guid, Status::Paused).await;
+    assert_eq!(runner.pause(guid).await, Ok(Status::Paused));
+    let checkpoint = runner.paused.lock().await[&guid]
+      .checkpoint
+      .clone()
+      .unwrap();
+    let (counted, _): (u32, u32) = serde_json::from_str(&checkpoint).unwrap();
+    assert!(0 < counted && counted < 20, "paused at {}", counted);
+
+    // The process carries on from where it stopped rather than starting over
+    assert_eq!(runner.run(guid).await, Ok(Status::Running));
+    let finished = serde_json::to_string(&(counted, 20)).unwrap();
+    runner.settle(guid, Status::Finished(finished)).await;
+  }
+
+  #[tokio::test]
+  async fn only_pausable_processes_can_be_paused_while_running() {
+    let runner = Arc::new(LongRunner::new(1));
+    let (running, _) = Gate::new(Ok("running"));
+    let running = runner.queue(Process::new(running)).await;
+    assert_eq!(
+      runner.pause(running).await,
+      Err(LongRunnerError::NotPausable(running))
+    );
+
+    // Anything still in line can be pulled out, and put back at the front
+    let (waiting, _) = Gate::new(Ok("waiting"));
+    let waiting = runner.queue(Process::new(waiting)).await;
+    assert_eq!(runner.pause(waiting).await, Ok(Status::Paused));
+    assert_eq!(runner.run(waiting).await, Ok(Status::Queued));
+  }
+
+  #[tokio::test]
+  async fn cancelled_processes_are_given_time_to_stop() {
+    let runner = Arc::new(LongRunner::new(1));
+    let ended = Arc::new(AtomicBool::new(false));
+    let guid = runner
+      .queue(Process::new(Stubborn {
+        listens: true,
+        grace_period: Some(Duration::from_secs(60)),
+        ended: ended.clone(),
+      }))
+      .await;
+
+    assert_eq!(runner.cancel(guid).await, Ok(Status::Running));
+    runner.settle(guid, Status::Stopped).await;
+    assert!(ended.load(Ordering::SeqCst));
+  }
+
+  #[tokio::test]
+  async fn cancelled_processes_are_killed_after_their_grace_period() {
+    let runner = Arc::new(LongRunner::new(1));
+    let ended = Arc::new(AtomicBool::new(false));
+    let guid = runner
+      .queue(Process::new(Stubborn {
+        listens: false,
+        grace_period: Some(Duration::from_millis(100)),
+        ended: ended.clone(),
+      }))
+      .await;
+
+    assert_eq!(runner.cancel(guid).await, Ok(Status::Running));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(runner.status(&guid).await, Some(Status::Running));
+    assert!(!ended.load(Ordering::SeqCst));
+
+    runner.settle(guid, Status::Stopped).await;
+    assert!(ended.load(Ordering::SeqCst));
+  }
+
+  #[tokio::test]
+  async fn cancelling_without_a_grace_period_kills() {
+    let runner = Arc::new(LongRunner::new(1));
+    let ended = Arc::new(AtomicBool::new(false));
+    let guid = runner
+      .queue(Process::new(Stubborn {
+        listens: false,
+        grace_period: None,
+        ended: ended.clone(),
+      }))
+      .await;
+
+    // Let the worker get into the process before it is stopped
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(runner.cancel(guid).await, Ok(Status::Stopped));
+    assert_eq!(runner.status(&guid).await, Some(Status::Stopped));
+    assert!(aborted(&ended).await);
+  }
+
+  #[tokio::test]
+  async fn killing_aborts_the_worker_and_frees_it() {
+    let runner = Arc::new(LongRunner::new(1));
+    let ended = Arc::new(AtomicBool::new(false));
+    let guid = runner
+      .queue(Process::new(Stubborn {
+        listens: true,
+        grace_period: Some(Duration::from_secs(60)),
+        ended: ended.clone(),
+      }))
+      .await;
+    let (next, _) = Gate::new(Ok("next"));
+    let next = runner.queue(Process::new(next)).await;
+    assert_eq!(runner.status(&next).await, Some(Status::Queued));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // No grace is given, even to a process that would have listened
+    assert_eq!(runner.kill(guid).await, Ok(Status::Stopped));
+    assert_eq!(runner.status(&guid).await, Some(Status::Stopped));
+    assert_eq!(runner.status(&next).await, Some(Status::Running));
+    assert!(aborted(&ended).await);
+
+    // Killing a process that never started takes it out of line
+    let (waiting, _) = Gate::new(Ok("waiting"));
+    let waiting = runner.queue(Process::new(waiting)).await;
+    assert_eq!(runner.kill(waiting).await, Ok(Status::Stopped));
+    assert_eq!(runner.status(&waiting).await, Some(Status::Stopped));
+  }
+
+  #[tokio::test]
+  async fn only_the_latest_finished_processes_are_kept() {
+    let runner = Arc::new(LongRunner::new(1));
+    let (blocking, _) = Gate::new(Ok("blocking"));
+    runner.queue(Process::new(blocking)).await;
+
+    let mut killed = Vec::new();
+    for _ in 0..=FINISHED_LIMIT {
+      let (waiting, _) = Gate::new(Ok("waiting"));
+      let waiting = runner.queue(Process::new(waiting)).await;
+      runner.kill(waiting).await.unwrap();
+      killed.push(waiting);
+    }
+
+    assert_eq!(runner.status(&killed[0]).await, None);
+    assert_eq!(runner.status(&killed[1]).await, Some(Status::Stopped));
+    assert_eq!(
+      runner.status(&killed[FINISHED_LIMIT]).await,
+      Some(Status::Stopped)
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn processes_can_be_found_while_they_complete() {
+    let runner = Arc::new(LongRunner::new(4));
+    let mut guids = Vec::new();
+    for _ in 0..16 {
+      let (gate, open) = Gate::new(Ok("done"));
+      guids.push(runner.queue(Process::new(gate)).await);
+      open.notify_one();
+    }
+
+    for _ in 0..500 {
+      let mut done = true;
+      for guid in &guids {
+        match runner.status(guid).await {
+          Some(Status::Finished(_)) => (),
+          Some(_) => done = false,
+          None => panic!("Process {} went missing", guid),
+        }
+      }
+      if done {
+        return;
+      }
+      tokio::task::yield_now().await;
+    }
+    panic!("The processes did not all finish");
+  }
+
+  #[tokio::test]
+  async fn the_emitter_hears_how_each_process_ends() {
+    let emitted = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let runner = {
+      let emitted = emitted.clone();
+      Arc::new(
+        LongRunner::new(2)
+          .with_emitter(move |guid, payload| emitted.lock().unwrap().push((guid, payload))),
+      )
+    };
+
+    let (finishing, open) = Gate::new(Ok("done"));
+    let finishing = runner.queue(Process::new(finishing)).await;
+    open.notify_one();
+    runner
+      .settle(finishing, Status::Finished("\"done\"".to_string()))
+      .await;
+
+    let (failing, open) = Gate::new(Err("broken"));
+    let failing = runner.queue(Process::new(failing)).await;
+    open.notify_one();
+    runner
+      .settle(failing, Status::Errored("broken".to_string()))
+      .await;
+
+    let (stopped, _) = Gate::new(Ok("never"));
+    let stopped = runner.queue(Process::new(stopped)).await;
+    runner.kill(stopped).await.unwrap();
+
+    let emitted = emitted.lock().unwrap().clone();
+    let ends: Vec<(Uuid, Payload)> = emitted
+      .iter()
+      .filter(|(_, payload)| !matches!(payload, Payload::Log(_)))
+      .cloned()
+      .collect();
+    assert_eq!(
+      ends,
+      vec![
+        (finishing, Payload::Result("\"done\"".to_string())),
+        (failing, Payload::Error("broken".to_string())),
+        (stopped, Payload::Stopped),
+      ]
+    );
+    // Emissions from inside a process are forwarded too
+    assert!(emitted.contains(&(finishing, Payload::Log("opened".to_string()))));
+  }
+}
@@ -1,29 +1,130 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::sync::Arc;
 use tauri::{
   menu::{MenuBuilder, MenuItemBuilder},
   tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
   Emitter, Manager,
 };
-use tracing::info;
+use tauri_plugin_shell::ShellExt;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use jiradoro_common::prelude::*;
 
+mod heartbeat;
+mod history;
+mod jira;
 mod longrunner;
+mod notifications;
+mod outbox;
+mod report;
+mod rounding;
+mod settings;
+mod timer;
+mod worklog;
 pub use longrunner::prelude::*;
 
+use heartbeat::Heartbeat;
+use history::History;
+use jira::oauth::{self, OAuthConfig};
+use jira::{JiraClient, Session};
+use longrunner::DEFAULT_WORKERS;
+use notifications::{DesktopNotifier, NotifyBoundaries};
+use outbox::{now, Outbox};
+use settings::SettingsStore;
+use timer::{RunTimer, Timer};
+use worklog::DeliverWorklogs;
+
+/// The most issues a search hands back to the picker
+const SEARCH_LIMIT: u32 = 20;
+
+/// The processes queued at startup that run for as long as the app does: delivering the outbox,
+/// running the timer and notifying the desktop. Each holds a worker of its own, so the runner gets
+/// this many on top of the usual workers left for the frontend's processes.
+const SERVICES: usize = 3;
+
 struct Server {
-  pub counter: i32,
+  long_runner: Arc<LongRunner>,
+  /// The Jira login, kept only in memory so the credentials never reach the frontend
+  jira: Arc<RwLock<Option<Session>>>,
+  /// The worklogs waiting to be delivered to Jira
+  outbox: Arc<Outbox>,
+  /// The process delivering the outbox
+  outbox_process: Uuid,
+  /// The Pomodoro timer, which the frontend only ever shows
+  timer: Arc<Timer>,
+  /// The process sending the timer out as it counts
+  timer_process: Uuid,
+  settings: Arc<SettingsStore>,
+  /// Every session and break, whether or not it made it into Jira
+  history: Arc<History>,
 }
 
 impl Server {
-  fn handle(&self, msg: String) -> String {
-    info!("Received msg: {}", msg);
+  /// Round a finished session with its project's rule and put what is logged in the outbox
+  fn record_work(&self, mut segment: WorkSegment) -> Response {
+    let rounding = self.settings.get().rounding;
+    let outcome = match rounding::outcome(&self.history, &rounding, &segment, &chrono::Local) {
+      Ok(outcome) => outcome,
+      Err(err) => return Response::Error(format!("Could not read the history: {}", err)),
+    };
+    let response = match outcome {
+      Outcome::Log(seconds) => {
+        segment.duration = seconds;
+        match self.outbox.push(segment.clone()) {
+          Ok(_) => Response::Outbox(self.outbox_process, self.outbox.status()),
+          Err(err) => return Response::Error(format!("Could not save the worklog: {}", err)),
+        }
+      }
+      outcome => Response::Rounded(self.outbox_process, outcome),
+    };
+    if let Err(err) = self.history.rounded(&segment, outcome) {
+      error!("Could not note the rounding in the history: {}", err);
+    }
+    response
+  }
 
-    // rs2js(format!("{Count: {}", self.counter));
-    format!("Counter Value {}", self.counter)
+  /// Apply a request from the client, describing the outcome in the response
+  async fn handle(&self, message: RequestMessage) -> Response {
+    let (guid, result) = match message {
+      RequestMessage::Heartbeat => return Response::Ack(Uuid::new_v4()),
+      RequestMessage::Queue(kind) => {
+        let process = match kind {
+          ProcessKind::Heartbeat => Process::new(Heartbeat::default()),
+        };
+        return Response::Ack(self.long_runner.queue(process).await);
+      }
+      RequestMessage::Run(guid) => (guid, self.long_runner.run(guid).await),
+      RequestMessage::Pause(guid) => (guid, self.long_runner.pause(guid).await),
+      RequestMessage::Cancel(guid) => (guid, self.long_runner.cancel(guid).await),
+      RequestMessage::Kill(guid) => (guid, self.long_runner.kill(guid).await),
+      RequestMessage::Status(guid) => (
+        guid,
+        self
+          .long_runner
+          .status(&guid)
+          .await
+          .ok_or(LongRunnerError::NotFound(guid)),
+      ),
+      RequestMessage::RecordWork(segment) => return self.record_work(segment),
+      RequestMessage::Outbox => return Response::Outbox(self.outbox_process, self.outbox.status()),
+      RequestMessage::RetryOutbox => {
+        self.outbox.retry();
+        return Response::Outbox(self.outbox_process, self.outbox.status());
+      }
+      RequestMessage::Timer => return Response::Timer(self.timer_process, self.timer.snapshot()),
+      RequestMessage::TimerCommand(command) => {
+        return Response::Timer(self.timer_process, self.timer.apply(command))
+      }
+    };
+
+    match result {
+      Ok(status) => Response::Status(guid, status.into()),
+      Err(err) => Response::Error(err.to_string()),
+    }
   }
 }
 
@@ -39,36 +140,253 @@ async fn set_title(_app_handle: tauri::AppHandle, _title: String) {
   }
 }
 
-/// Receive a message from the client and forwards it along to the server side. Messages are passed
-/// along serialized, leaving it to the server to fully process them.
+/// Hold on to a new Jira login, replacing any existing one
+async fn start_session(app: &tauri::AppHandle, session: Session) -> JiraUser {
+  let user = session.user.clone();
+  info!(
+    "Logged in to {} as {}",
+    session.client.site(),
+    user.display_name
+  );
+
+  let state = app.state::<State>();
+  *state.server.jira.write().await = Some(session);
+  // Anything waiting in the outbox can go out now that there is a login to send it with
+  state.server.outbox.wake();
+  user
+}
+
+/// Log in to a Jira Cloud site with an API token, replacing any existing login once Jira has
+/// accepted the credentials.
 #[tauri::command]
-fn call_server(message: String, state: tauri::State<'_, State>, app: tauri::AppHandle) -> String {
-  info!(?message, "Received tauri::command::call_server - ");
-  // Let's emit an event that should be caught by the frontend window
-  let guid = Uuid::new_v4();
-  app
-    .emit(
-      "Emission",
-      Emission {
-        guid,
-        message: Response::Ack(guid),
-      },
-    )
-    .unwrap();
+async fn jira_login(login: ApiTokenLogin, app: tauri::AppHandle) -> Result<JiraUser, String> {
+  info!(
+    site = %login.site,
+    email = %login.email,
+    "Received tauri::command::jira_login - "
+  );
+  let session = JiraClient::login(&login)
+    .await
+    .map_err(|err| err.to_string())?;
 
-  state.server.handle(message)
+  // Remember the site for the next time the login form is filled in
+  let state = app.state::<State>();
+  let site = Some(login.site.clone());
+  if let Err(err) = state
+    .server
+    .settings
+    .update(|settings| settings.jira_site = site)
+  {
+    warn!("Could not remember the Jira site: {}", err);
+  }
+  Ok(start_session(&app, session).await)
 }
 
-fn main() {
-  tracing_subscriber::fmt::init();
+/// Log in through Atlassian's OAuth 2.0 flow for orgs that do not allow API tokens. The user
+/// authorizes the app in the system browser, which is then sent back to a loopback listener.
+#[tauri::command]
+async fn jira_oauth_login(app: tauri::AppHandle) -> Result<JiraUser, String> {
+  info!("Received tauri::command::jira_oauth_login - ");
+  let config = OAuthConfig::from_env().map_err(|err| err.to_string())?;
+  let shell = app.shell();
+  let (session, sites) = oauth::login(config, |url| {
+    shell
+      .open(url.as_str(), None)
+      .map_err(|err| err.to_string())
+  })
+  .await
+  .map_err(|err| err.to_string())?;
+
+  info!(
+    "Access was granted to {}",
+    sites
+      .iter()
+      .map(|site| format!("{} ({})", site.name, site.id))
+      .collect::<Vec<String>>()
+      .join(", ")
+  );
+  Ok(start_session(&app, session).await)
+}
+
+/// Forget the Jira login along with its credentials
+#[tauri::command]
+async fn jira_logout(app: tauri::AppHandle) {
+  info!("Received tauri::command::jira_logout - ");
+  let state = app.state::<State>();
+  state.server.jira.write().await.take();
+}
+
+/// The Jira account currently logged in, if any
+#[tauri::command]
+async fn jira_user(app: tauri::AppHandle) -> Option<JiraUser> {
+  let state = app.state::<State>();
+  let jira = state.server.jira.read().await;
+  jira.as_ref().map(|session| session.user.clone())
+}
+
+/// Search for issues to work on in the Jira the app is logged in to
+#[tauri::command]
+async fn jira_search(
+  query: IssueQuery,
+  app: tauri::AppHandle,
+) -> Result<Vec<IssueSummary>, String> {
+  let jql = query.jql();
+  info!(%jql, "Received tauri::command::jira_search - ");
+  let state = app.state::<State>();
+  let client = match state.server.jira.read().await.as_ref() {
+    Some(session) => session.client.clone(),
+    None => return Err("Log in to Jira to search for issues".to_string()),
+  };
+  client
+    .search(&jql, SEARCH_LIMIT)
+    .await
+    .map_err(|err| err.to_string())
+}
+
+/// The user's settings as they were last saved
+#[tauri::command]
+async fn get_settings(app: tauri::AppHandle) -> Settings {
+  let state = app.state::<State>();
+  state.server.settings.get()
+}
 
-  let state = State {
-    server: Server { counter: 0 },
+/// Replace the user's settings, putting the timer's straight into effect
+#[tauri::command]
+async fn save_settings(settings: Settings, app: tauri::AppHandle) -> Result<Settings, String> {
+  info!("Received tauri::command::save_settings - ");
+  let state = app.state::<State>();
+  let saved = state
+    .server
+    .settings
+    .update(|current| *current = settings)
+    .map_err(|err| format!("Could not save the settings: {}", err))?;
+  state
+    .server
+    .timer
+    .apply(TimerCommand::Configure(saved.timer.clone()));
+  Ok(saved)
+}
+
+/// What has been logged to the issue's project today and held on the issue, which the rounding
+/// takes into account
+#[tauri::command]
+async fn worklog_ledger(issue_key: String, app: tauri::AppHandle) -> Result<Ledger, String> {
+  let state = app.state::<State>();
+  rounding::ledger(&state.server.history, &issue_key, now(), &chrono::Local)
+    .map_err(|err| format!("Could not read the history: {}", err))
+}
+
+/// Look up sessions and breaks in the local history
+#[tauri::command]
+async fn history_segments(
+  query: HistoryQuery,
+  app: tauri::AppHandle,
+) -> Result<Vec<Segment>, String> {
+  info!(?query, "Received tauri::command::history_segments - ");
+  let state = app.state::<State>();
+  state
+    .server
+    .history
+    .query(&query)
+    .map_err(|err| format!("Could not read the history: {}", err))
+}
+
+/// Report on the sessions started between the two times, grouped by the local day and week
+#[tauri::command]
+async fn history_report(from: u64, to: u64, app: tauri::AppHandle) -> Result<Report, String> {
+  info!(from, to, "Received tauri::command::history_report - ");
+  let state = app.state::<State>();
+  let query = HistoryQuery {
+    from: Some(from),
+    to: Some(to),
+    ..HistoryQuery::default()
   };
+  let segments = state
+    .server
+    .history
+    .query(&query)
+    .map_err(|err| format!("Could not read the history: {}", err))?;
+  Ok(report::build(&segments, from, to, &chrono::Local))
+}
+
+/// Receive a message from the client and forwards it along to the server side, replying with the
+/// outcome of the request.
+#[tauri::command]
+async fn call_server(message: RequestMessage, app: tauri::AppHandle) -> Response {
+  info!(?message, "Received tauri::command::call_server - ");
+  let state = app.state::<State>();
+  state.server.handle(message).await
+}
+
+fn main() {
+  tracing_subscriber::fmt::init();
 
   tauri::Builder::default()
-    .manage(state)
+    .plugin(tauri_plugin_shell::init())
     .setup(|app| {
+      // Forward everything emitted by a running process to the frontend's root listener
+      let handle = app.handle().clone();
+      let long_runner =
+        LongRunner::new(SERVICES + DEFAULT_WORKERS).with_emitter(move |guid, payload| {
+          let emission = Emission {
+            guid,
+            message: Response::Payload(payload),
+          };
+          if let Err(err) = handle.emit("Emission", emission) {
+            error!("Could not send the emission for {}: {}", guid, err);
+          }
+        });
+      let long_runner = Arc::new(long_runner);
+
+      // Deliver the worklogs left over from earlier runs, along with any recorded from now on
+      let data_dir = app.path().app_data_dir()?;
+      std::fs::create_dir_all(&data_dir)?;
+      let outbox = Arc::new(Outbox::open(data_dir.join("outbox.json"))?);
+      let history = Arc::new(History::open(data_dir.join("history.sqlite3"))?);
+      let jira = Arc::new(RwLock::new(None));
+      let delivery = Process::new(DeliverWorklogs {
+        outbox: outbox.clone(),
+        jira: jira.clone(),
+        history: history.clone(),
+      });
+      let outbox_process = tauri::async_runtime::block_on(long_runner.queue(delivery));
+
+      let config_dir = app.path().app_config_dir()?;
+      std::fs::create_dir_all(&config_dir)?;
+      let settings = Arc::new(SettingsStore::open(config_dir.join("settings.json"))?);
+
+      // Keep the timer counting for as long as the app runs, whatever the window is doing
+      let timer = Arc::new(Timer::new(settings.get().timer, history.clone()));
+      // Sessions are tracked against the default issue until another one is picked
+      if let Some(issue) = settings.get().default_issue {
+        timer.apply(TimerCommand::SetIssue(Some(issue.key)));
+      }
+      let ticking = Process::new(RunTimer {
+        timer: timer.clone(),
+      });
+      let timer_process = tauri::async_runtime::block_on(long_runner.queue(ticking));
+
+      // Notify the desktop as sessions and breaks run out, even with the window hidden
+      let notifying = Process::new(NotifyBoundaries {
+        timer: timer.clone(),
+        notifier: Arc::new(DesktopNotifier),
+        settings: settings.clone(),
+      });
+      tauri::async_runtime::block_on(long_runner.queue(notifying));
+
+      app.manage(State {
+        server: Server {
+          long_runner,
+          jira,
+          outbox,
+          outbox_process,
+          timer,
+          timer_process,
+          settings,
+          history,
+        },
+      });
+
       let toggle = MenuItemBuilder::with_id("toggle", "Toggle").build(app)?;
       let menu = MenuBuilder::new(app).items(&[&toggle]).build()?;
       let _tray = TrayIconBuilder::new()
@@ -105,7 +423,20 @@ fn main() {
 
       Ok(())
     })
-    .invoke_handler(tauri::generate_handler![call_server, set_title])
+    .invoke_handler(tauri::generate_handler![
+      call_server,
+      get_settings,
+      history_report,
+      history_segments,
+      jira_login,
+      jira_logout,
+      jira_oauth_login,
+      jira_search,
+      jira_user,
+      save_settings,
+      set_title,
+      worklog_ledger
+    ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
 }
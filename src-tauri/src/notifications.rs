@@ -0,0 +1,428 @@
+//! Desktop notifications for the moment a session or break runs out, so it is noticed with the
+//! window hidden in the tray. The actions on a notification feed straight back into the timer.
+//!
+//! Notifications are shown through a `Notifier`, which is the freedesktop notification service on
+//! Linux and can be faked in tests. The `NotifyBoundaries` process listens for the timer's
+//! boundaries for as long as the app runs.
+
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::longrunner::prelude::*;
+use crate::settings::SettingsStore;
+use crate::timer::{Boundary, Timer};
+use jiradoro_common::prelude::*;
+
+/// What can be done from a notification
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Action {
+  StartBreak,
+  /// Keep the session's worklog and start another session without a break
+  LogAndContinue,
+  StartSession,
+  /// Show the notification again a few minutes later
+  Snooze,
+}
+
+impl Action {
+  const ALL: [Action; 4] = [
+    Action::StartBreak,
+    Action::LogAndContinue,
+    Action::StartSession,
+    Action::Snooze,
+  ];
+
+  /// How the action is told apart when the notification service reports it was picked
+  fn id(self) -> &'static str {
+    match self {
+      Action::StartBreak => "start-break",
+      Action::LogAndContinue => "log-and-continue",
+      Action::StartSession => "start-session",
+      Action::Snooze => "snooze",
+    }
+  }
+
+  #[cfg_attr(not(all(unix, not(target_os = "macos"))), allow(dead_code))]
+  fn from_id(id: &str) -> Option<Action> {
+    Action::ALL.into_iter().find(|action| action.id() == id)
+  }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Notification {
+  pub title: String,
+  pub body: String,
+  /// The actions offered, each with its label
+  pub actions: Vec<(Action, String)>,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+  /// Show the notification, waiting for the action picked from it. Nothing is picked when it is
+  /// dismissed, or when the desktop can't offer actions.
+  async fn notify(&self, notification: Notification) -> Option<Action>;
+}
+
+/// The desktop's own notifications
+pub struct DesktopNotifier;
+
+/// How far a desktop notification has got, shared with the thread waiting on it
+#[derive(Default)]
+struct Showing {
+  /// The desktop's id for the notification, once it is shown
+  id: Option<u32>,
+  /// Whether an action was picked or the notification was closed on the desktop
+  answered: bool,
+  /// Whether the notification is no longer wanted, so it should not be shown if it hasn't been
+  withdrawn: bool,
+}
+
+/// Takes the notification down if it is dropped before it is answered, as happens when a newer
+/// boundary replaces it. Otherwise it would stay up doing nothing, with a thread still waiting on it.
+struct Withdraw(Arc<Mutex<Showing>>);
+
+impl Drop for Withdraw {
+  fn drop(&mut self) {
+    let mut showing = self.0.lock().unwrap();
+    if showing.answered {
+      return;
+    }
+    showing.withdrawn = true;
+    if let Some(id) = showing.id {
+      tokio::task::spawn_blocking(move || close(id));
+    }
+  }
+}
+
+#[async_trait]
+impl Notifier for DesktopNotifier {
+  async fn notify(&self, notification: Notification) -> Option<Action> {
+    let showing = Arc::new(Mutex::new(Showing::default()));
+    let _withdraw = Withdraw(showing.clone());
+    // Waiting for an action blocks, so it is kept off the async workers
+    match tokio::task::spawn_blocking(move || show(notification, &showing)).await {
+      Ok(Ok(action)) => action,
+      Ok(Err(err)) => {
+        warn!("Could not show a notification: {}", err);
+        None
+      }
+      Err(err) => {
+        warn!("Showing a notification failed: {}", err);
+        None
+      }
+    }
+  }
+}
+
+/// Show the notification through the freedesktop notification service and wait for it to close
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show(
+  notification: Notification,
+  showing: &Mutex<Showing>,
+) -> Result<Option<Action>, notify_rust::error::Error> {
+  let mut desktop = notify_rust::Notification::new();
+  desktop
+    .appname("Jiradoro")
+    .summary(&notification.title)
+    .body(&notification.body);
+  for (action, label) in &notification.actions {
+    desktop.action(action.id(), label);
+  }
+  let handle = {
+    let mut showing = showing.lock().unwrap();
+    if showing.withdrawn {
+      return Ok(None);
+    }
+    let handle = desktop.show()?;
+    showing.id = Some(handle.id());
+    handle
+  };
+  let mut picked = None;
+  handle.wait_for_action(|id| picked = Action::from_id(id));
+  showing.lock().unwrap().answered = true;
+  Ok(picked)
+}
+
+/// Close a notification shown earlier. Only the handle that showed a notification can close it, and
+/// that is taken by the thread waiting for its action, so the notification is replaced through a new
+/// handle which is closed instead. The waiting thread hears it close and finishes.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn close(id: u32) {
+  match notify_rust::Notification::new()
+    .appname("Jiradoro")
+    .id(id)
+    .show()
+  {
+    Ok(handle) => handle.close(),
+    Err(err) => warn!("Could not close notification {}: {}", id, err),
+  }
+}
+
+/// Elsewhere notifications can't offer actions, so they only say what happened
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn show(
+  notification: Notification,
+  _showing: &Mutex<Showing>,
+) -> Result<Option<Action>, notify_rust::error::Error> {
+  notify_rust::Notification::new()
+    .appname("Jiradoro")
+    .summary(&notification.title)
+    .body(&notification.body)
+    .show()?;
+  Ok(None)
+}
+
+/// Nothing waits on a notification elsewhere, so there is never one to close
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn close(_id: u32) {}
+
+/// The notification for a boundary, offering whatever moves the timer on from where it is now
+fn notification(boundary: Boundary, timer: &TimerSnapshot, snooze_minutes: u64) -> Notification {
+  let snooze = (Action::Snooze, format!("Snooze {} min", snooze_minutes));
+  let action = |action: Action, label: &str| (action, label.to_string());
+  let (title, body, actions) = match (boundary, timer.state) {
+    // Auto start has already moved on to the break
+    (Boundary::SessionFinished, TimerState::Break | TimerState::LongBreak) => (
+      "Session finished",
+      "Your break has started.",
+      vec![action(Action::LogAndContinue, "Skip the break")],
+    ),
+    (Boundary::SessionFinished, _) => (
+      "Session finished",
+      "Time for a break.",
+      vec![
+        action(Action::StartBreak, "Start break"),
+        action(Action::LogAndContinue, "Log and continue"),
+        snooze,
+      ],
+    ),
+    (Boundary::BreakFinished, TimerState::Running) => {
+      ("Break over", "The next session has started.", Vec::new())
+    }
+    (Boundary::BreakFinished, _) => (
+      "Break over",
+      "Time to get back to work.",
+      vec![action(Action::StartSession, "Start session"), snooze],
+    ),
+  };
+  Notification {
+    title: title.to_string(),
+    body: body.to_string(),
+    actions,
+  }
+}
+
+pub struct NotifyBoundaries {
+  pub timer: Arc<Timer>,
+  pub notifier: Arc<dyn Notifier>,
+  /// Whether to notify at all, and for how long a snooze lasts
+  pub settings: Arc<SettingsStore>,
+}
+
+#[async_trait]
+impl LongRunnerProcess for NotifyBoundaries {
+  /// The number of notifications shown before the process was stopped
+  type Output = u64;
+  type Error = Infallible;
+
+  async fn start(self, tools: ProcessTools) -> Result<u64, Infallible> {
+    let mut boundaries = self.timer.boundaries();
+    let mut next = None;
+    let mut shown = 0;
+
+    loop {
+      let boundary = match next.take() {
+        Some(boundary) => boundary,
+        None => tokio::select! {
+          _ = tools.cancellation_token.cancelled() => break,
+          received = boundaries.recv() => match received {
+            Ok(boundary) => boundary,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+          },
+        },
+      };
+      let settings = self.settings.get().notifications;
+      if !settings.enabled {
+        continue;
+      }
+
+      let timer = self.timer.snapshot();
+      let shown_for = (timer.state, timer.started);
+      let notification = notification(boundary, &timer, settings.snooze_minutes);
+      shown += 1;
+      // A newer boundary replaces the notification still waiting for an answer
+      let action = tokio::select! {
+        _ = tools.cancellation_token.cancelled() => break,
+        Ok(boundary) = boundaries.recv() => {
+          next = Some(boundary);
+          continue;
+        }
+        action = self.notifier.notify(notification) => action,
+      };
+      info!("Picked {:?} from the {:?} notification", action, boundary);
+
+      let command = match action {
+        Some(Action::StartBreak) => TimerCommand::Break,
+        Some(Action::LogAndContinue) => TimerCommand::Continue,
+        Some(Action::StartSession) => TimerCommand::Start,
+        Some(Action::Snooze) => {
+          let snooze = Duration::from_secs(settings.snooze_minutes * 60);
+          tokio::select! {
+            _ = tools.cancellation_token.cancelled() => break,
+            Ok(boundary) = boundaries.recv() => next = Some(boundary),
+            _ = tokio::time::sleep(snooze) => {
+              // Remind again unless the timer has been moved on in the meantime
+              let timer = self.timer.snapshot();
+              if (timer.state, timer.started) == shown_for && timer.finished() {
+                next = Some(boundary);
+              }
+            }
+          }
+          continue;
+        }
+        None => continue,
+      };
+      self.timer.apply(command);
+    }
+
+    info!("Showed {} notifications before stopping", shown);
+    Ok(shown)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::history::History;
+  use tokio::sync::{mpsc, Mutex};
+  use tokio_util::sync::CancellationToken;
+  use uuid::Uuid;
+
+  /// Hands each notification to the test and answers with whatever the test sends back
+  struct FakeNotifier {
+    shown: mpsc::Sender<Notification>,
+    answers: Mutex<mpsc::Receiver<Option<Action>>>,
+  }
+
+  #[async_trait]
+  impl Notifier for FakeNotifier {
+    async fn notify(&self, notification: Notification) -> Option<Action> {
+      self.shown.send(notification).await.unwrap();
+      self.answers.lock().await.recv().await.flatten()
+    }
+  }
+
+  fn tools(cancellation_token: CancellationToken) -> ProcessTools {
+    ProcessTools {
+      guid: Uuid::new_v4(),
+      requests: mpsc::channel(1).1,
+      emissions: mpsc::channel(16).0,
+      log: Default::default(),
+      cancellation_token,
+    }
+  }
+
+  fn snapshot(state: TimerState, elapsed: u64) -> TimerSnapshot {
+    TimerSnapshot {
+      state,
+      elapsed,
+      length: 25 * 60,
+      ..TimerSnapshot::default()
+    }
+  }
+
+  #[test]
+  fn offers_whatever_moves_the_timer_on() {
+    let actions = |boundary, state| -> Vec<Action> {
+      let notification = notification(boundary, &snapshot(state, 25 * 60), 5);
+      notification
+        .actions
+        .into_iter()
+        .map(|(action, _)| action)
+        .collect()
+    };
+    assert_eq!(
+      actions(Boundary::SessionFinished, TimerState::Running),
+      vec![Action::StartBreak, Action::LogAndContinue, Action::Snooze]
+    );
+    assert_eq!(
+      actions(Boundary::BreakFinished, TimerState::Break),
+      vec![Action::StartSession, Action::Snooze]
+    );
+    // Auto start has moved on already, so there is less to do
+    assert_eq!(
+      actions(Boundary::SessionFinished, TimerState::Break),
+      vec![Action::LogAndContinue]
+    );
+    assert!(actions(Boundary::BreakFinished, TimerState::Running).is_empty());
+
+    let notification = notification(
+      Boundary::SessionFinished,
+      &snapshot(TimerState::Running, 0),
+      10,
+    );
+    assert_eq!(notification.actions[2].1, "Snooze 10 min");
+  }
+
+  #[tokio::test]
+  async fn actions_feed_back_into_the_timer() {
+    let settings = TimerSettings {
+      session_length: 1,
+      ..TimerSettings::default()
+    };
+    let history = Arc::new(History::open(":memory:").unwrap());
+    let timer = Arc::new(Timer::new(settings, history));
+    let file = std::env::temp_dir().join(format!("jiradoro-settings-{}.json", Uuid::new_v4()));
+    let settings = Arc::new(SettingsStore::open(file).unwrap());
+
+    let (shown, mut notifications) = mpsc::channel(1);
+    let (answer, answers) = mpsc::channel(1);
+    let notifier = FakeNotifier {
+      shown,
+      answers: Mutex::new(answers),
+    };
+    let cancel = CancellationToken::new();
+    let process = NotifyBoundaries {
+      timer: timer.clone(),
+      notifier: Arc::new(notifier),
+      settings,
+    };
+    let running = tokio::spawn(process.start(tools(cancel.clone())));
+
+    // The timer only notices the session running out when it is looked at, as `RunTimer` does
+    let ticking = {
+      let timer = timer.clone();
+      tokio::spawn(async move {
+        loop {
+          timer.snapshot();
+          tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+      })
+    };
+
+    timer.apply(TimerCommand::Start);
+    let notification = notifications.recv().await.unwrap();
+    assert_eq!(notification.title, "Session finished");
+    answer.send(Some(Action::StartBreak)).await.unwrap();
+
+    // The break is started from the notification
+    let mut state = timer.snapshot().state;
+    for _ in 0..20 {
+      if state == TimerState::Break {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      state = timer.snapshot().state;
+    }
+    assert_eq!(state, TimerState::Break);
+
+    ticking.abort();
+    cancel.cancel();
+    assert_eq!(running.await.unwrap().unwrap(), 1);
+  }
+}
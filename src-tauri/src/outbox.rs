@@ -0,0 +1,595 @@
+//! The worklogs waiting to be delivered to Jira, kept on disk so none are lost when Jira can't be
+//! reached or the app is closed before they are sent.
+//!
+//! Each entry has an id that is stored on the worklog in Jira. An entry is marked as sent on disk
+//! before its first request goes out, and from then on it is only sent again after checking that no
+//! worklog with its id has already been recorded. A request that reached Jira but lost its response,
+//! or was still on its way when the app was closed, never logs the time twice.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::jira::{JiraClient, JiraError};
+use jiradoro_common::prelude::*;
+
+/// The version of the file layout, bumped whenever it changes
+const FILE_VERSION: u32 = 1;
+
+/// The wait before the first retry, doubled with every attempt after it
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The longest wait between attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// Milliseconds since the unix epoch
+pub fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+/// How long to wait after the given number of failed attempts
+pub fn backoff(attempts: u32) -> Duration {
+  let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+  BASE_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// Whether the problem is with the request itself, which sending it again will not fix: the issue
+/// is missing, the worklog is invalid, logging work on the issue is forbidden, or the issue key was
+/// never one to begin with
+fn refused(err: &JiraError) -> bool {
+  match err {
+    JiraError::Status { code, .. } => (400..500).contains(code),
+    JiraError::InvalidIssueKey(_) => true,
+    _ => false,
+  }
+}
+
+/// A worklog waiting to be delivered
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+  /// Recorded with the worklog in Jira to tell whether an earlier attempt made it there
+  pub id: Uuid,
+  pub segment: WorkSegment,
+  /// The number of attempts made so far
+  pub attempts: u32,
+  /// Set before the first request goes out, as from then on Jira may already have the worklog
+  #[serde(default)]
+  pub sent: bool,
+  /// When the entry can next be tried, in milliseconds since the unix epoch
+  pub next_attempt: u64,
+  /// Why the latest attempt failed
+  pub last_error: Option<String>,
+  /// Set once Jira has refused the worklog in a way retrying won't fix
+  pub failed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutboxFile {
+  version: u32,
+  entries: Vec<OutboxEntry>,
+}
+
+/// What happened when an entry was sent
+#[derive(PartialEq, Debug)]
+pub enum Delivery {
+  Delivered(Worklog),
+  /// The entry is to be tried again at the given time
+  Retrying {
+    at: u64,
+    error: JiraError,
+  },
+  /// The entry won't be tried again unless it is retried by hand
+  Failed(JiraError),
+}
+
+pub struct Outbox {
+  path: PathBuf,
+  entries: Mutex<Vec<OutboxEntry>>,
+  /// Wakes the delivery process when there is something new to try
+  changed: Notify,
+}
+
+impl Outbox {
+  /// Open the outbox stored at the path, starting an empty one if there isn't one yet. A file that
+  /// can't be read is set aside rather than overwritten so the worklogs in it can be recovered.
+  pub fn open(path: impl AsRef<Path>) -> io::Result<Outbox> {
+    let path = path.as_ref().to_path_buf();
+    let entries = match fs::read_to_string(&path) {
+      Ok(contents) => match serde_json::from_str::<OutboxFile>(&contents) {
+        Ok(file) if file.version == FILE_VERSION => file.entries,
+        Ok(file) => {
+          error!("Outbox version {} is not supported", file.version);
+          Self::set_aside(&path)?
+        }
+        Err(err) => {
+          error!("Could not read the outbox at {}: {}", path.display(), err);
+          Self::set_aside(&path)?
+        }
+      },
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+      Err(err) => return Err(err),
+    };
+    info!("Opened the outbox with {} worklogs", entries.len());
+
+    Ok(Outbox {
+      path,
+      entries: Mutex::new(entries),
+      changed: Notify::new(),
+    })
+  }
+
+  fn set_aside(path: &Path) -> io::Result<Vec<OutboxEntry>> {
+    let aside = path.with_extension(format!("unreadable-{}", now()));
+    warn!("Moving the outbox to {}", aside.display());
+    fs::rename(path, aside)?;
+    Ok(Vec::new())
+  }
+
+  /// Write the entries out, replacing the file only once they are fully written
+  fn save(&self, entries: &[OutboxEntry]) -> io::Result<()> {
+    let file = OutboxFile {
+      version: FILE_VERSION,
+      entries: entries.to_vec(),
+    };
+    let contents = serde_json::to_string_pretty(&file)?;
+    let partial = self.path.with_extension("partial");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, &self.path)
+  }
+
+  /// Apply a change to the entries, keeping the file in step with them
+  fn update<T>(&self, change: impl FnOnce(&mut Vec<OutboxEntry>) -> T) -> T {
+    let mut entries = self.entries.lock().unwrap();
+    let result = change(&mut entries);
+    if let Err(err) = self.save(&entries) {
+      error!(
+        "Could not save the outbox to {}: {}",
+        self.path.display(),
+        err
+      );
+    }
+    result
+  }
+
+  /// Add a worklog to be delivered as soon as possible
+  pub fn push(&self, segment: WorkSegment) -> io::Result<Uuid> {
+    let entry = OutboxEntry {
+      id: Uuid::new_v4(),
+      segment,
+      attempts: 0,
+      sent: false,
+      next_attempt: 0,
+      last_error: None,
+      failed: false,
+    };
+    let id = entry.id;
+
+    // Adding a worklog has to make it to disk, or it could be lost without anyone knowing
+    {
+      let mut entries = self.entries.lock().unwrap();
+      entries.push(entry);
+      if let Err(err) = self.save(&entries) {
+        entries.pop();
+        return Err(err);
+      }
+    }
+
+    info!("Added worklog {} to the outbox", id);
+    self.changed.notify_one();
+    Ok(id)
+  }
+
+  pub fn status(&self) -> OutboxStatus {
+    let entries = self.entries.lock().unwrap();
+    OutboxStatus {
+      pending: entries.iter().filter(|entry| !entry.failed).count(),
+      failed: entries.iter().filter(|entry| entry.failed).count(),
+      last_error: entries
+        .iter()
+        .rev()
+        .find_map(|entry| entry.last_error.clone()),
+    }
+  }
+
+  /// Give every failed entry another go, and try the waiting ones straight away
+  pub fn retry(&self) {
+    self.update(|entries| {
+      for entry in entries.iter_mut() {
+        entry.failed = false;
+        entry.next_attempt = 0;
+      }
+    });
+    self.changed.notify_one();
+  }
+
+  /// Wake the delivery process, such as after logging in to Jira
+  pub fn wake(&self) {
+    self.changed.notify_one();
+  }
+
+  /// Wait until something changes that is worth another look at the entries
+  pub async fn changed(&self) {
+    self.changed.notified().await
+  }
+
+  /// The first entry that is due to be tried
+  pub fn due(&self, now: u64) -> Option<OutboxEntry> {
+    let entries = self.entries.lock().unwrap();
+    entries
+      .iter()
+      .filter(|entry| !entry.failed && entry.next_attempt <= now)
+      .min_by_key(|entry| entry.next_attempt)
+      .cloned()
+  }
+
+  /// When the next waiting entry is due, in milliseconds since the unix epoch
+  pub fn next_due(&self) -> Option<u64> {
+    let entries = self.entries.lock().unwrap();
+    entries
+      .iter()
+      .filter(|entry| !entry.failed)
+      .map(|entry| entry.next_attempt)
+      .min()
+  }
+
+  /// Try to get the entry into Jira, keeping it around to try again if it didn't make it
+  pub async fn deliver(&self, client: &JiraClient, entry: &OutboxEntry, now: u64) -> Delivery {
+    // Noted before the request goes out, so a request cut off by the app closing is checked for
+    // next time rather than sent blindly again
+    // Entries saved before `sent` was kept count as sent once they have been tried
+    let result = match entry.sent || entry.attempts > 0 {
+      false => {
+        self.update(|entries| {
+          if let Some(current) = entries.iter_mut().find(|current| current.id == entry.id) {
+            current.sent = true;
+          }
+        });
+        client.add_worklog(entry.id, &entry.segment).await
+      }
+      true => match client.find_worklog(entry.id, &entry.segment).await {
+        Ok(Some(worklog)) => {
+          info!(
+            "Worklog {} was already recorded as {}",
+            entry.id, worklog.id
+          );
+          Ok(worklog)
+        }
+        Ok(None) => client.add_worklog(entry.id, &entry.segment).await,
+        Err(err) => Err(err),
+      },
+    };
+
+    self.update(|entries| {
+      let index = match entries.iter().position(|current| current.id == entry.id) {
+        Some(index) => index,
+        None => {
+          warn!(
+            "Worklog {} left the outbox while it was being sent",
+            entry.id
+          );
+          return match result {
+            Ok(worklog) => Delivery::Delivered(worklog),
+            Err(err) => Delivery::Failed(err),
+          };
+        }
+      };
+
+      let err = match result {
+        Ok(worklog) => {
+          entries.remove(index);
+          return Delivery::Delivered(worklog);
+        }
+        Err(err) => err,
+      };
+
+      let current = &mut entries[index];
+      current.attempts += 1;
+      current.last_error = Some(err.to_string());
+      if refused(&err) {
+        warn!("Jira refused worklog {}: {}", entry.id, err);
+        current.failed = true;
+        return Delivery::Failed(err);
+      }
+
+      let wait = match err {
+        JiraError::RateLimited {
+          retry_after: Some(seconds),
+        } => Duration::from_secs(seconds),
+        _ => backoff(current.attempts),
+      };
+      current.next_attempt = now + wait.as_millis() as u64;
+      info!(
+        "Retrying worklog {} in {} seconds: {}",
+        entry.id,
+        wait.as_secs(),
+        err
+      );
+      Delivery::Retrying {
+        at: current.next_attempt,
+        error: err,
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::jira::Auth;
+  use wiremock::matchers::{method, path};
+  use wiremock::{Mock, MockServer, ResponseTemplate};
+
+  impl Outbox {
+    fn entries(&self) -> Vec<OutboxEntry> {
+      self.entries.lock().unwrap().clone()
+    }
+  }
+
+  /// A path for an outbox file that no other test is using
+  fn scratch_file() -> PathBuf {
+    std::env::temp_dir().join(format!("jiradoro-outbox-{}.json", Uuid::new_v4()))
+  }
+
+  fn segment() -> WorkSegment {
+    WorkSegment {
+      issue_key: "JRD-12".to_string(),
+      started: 1_724_148_000_000,
+      duration: 1500,
+      comment: None,
+      segment: None,
+    }
+  }
+
+  fn client(server: &MockServer) -> JiraClient {
+    let auth = Auth::ApiToken {
+      email: String::new(),
+      token: String::new(),
+    };
+    JiraClient::new(&server.uri(), auth).unwrap()
+  }
+
+  fn created() -> ResponseTemplate {
+    ResponseTemplate::new(201).set_body_json(serde_json::json!({
+      "id": "100028",
+      "timeSpentSeconds": 1500,
+    }))
+  }
+
+  #[test]
+  fn entries_survive_a_restart() {
+    let file = scratch_file();
+    let id = Outbox::open(&file).unwrap().push(segment()).unwrap();
+
+    let reopened = Outbox::open(&file).unwrap();
+    let entries = reopened.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, id);
+    assert_eq!(entries[0].segment, segment());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn unreadable_files_are_set_aside() {
+    let file = scratch_file();
+    fs::write(&file, "not an outbox").unwrap();
+
+    let outbox = Outbox::open(&file).unwrap();
+    assert!(outbox.entries().is_empty());
+    assert!(!file.exists());
+
+    let prefix = file.file_stem().unwrap().to_string_lossy().to_string();
+    let aside: Vec<_> = fs::read_dir(std::env::temp_dir())
+      .unwrap()
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+      .collect();
+    assert_eq!(aside.len(), 1);
+    fs::remove_file(aside[0].path()).unwrap();
+  }
+
+  #[test]
+  fn backoff_doubles_up_to_the_limit() {
+    assert_eq!(backoff(1), Duration::from_secs(5));
+    assert_eq!(backoff(2), Duration::from_secs(10));
+    assert_eq!(backoff(4), Duration::from_secs(40));
+    assert_eq!(backoff(30), MAX_BACKOFF);
+  }
+
+  #[tokio::test]
+  async fn delivered_entries_leave_the_outbox() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .and(path("/rest/api/3/issue/JRD-12/worklog"))
+      .respond_with(created())
+      .expect(1)
+      .mount(&server)
+      .await;
+
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    outbox.push(segment()).unwrap();
+
+    let entry = outbox.due(now()).unwrap();
+    assert!(matches!(
+      outbox.deliver(&client(&server), &entry, now()).await,
+      Delivery::Delivered(_)
+    ));
+    assert!(Outbox::open(&file).unwrap().entries().is_empty());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn rate_limits_are_honored() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "42"))
+      .mount(&server)
+      .await;
+
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    outbox.push(segment()).unwrap();
+
+    let entry = outbox.due(1_000).unwrap();
+    assert_eq!(
+      outbox.deliver(&client(&server), &entry, 1_000).await,
+      Delivery::Retrying {
+        at: 43_000,
+        error: JiraError::RateLimited {
+          retry_after: Some(42)
+        },
+      }
+    );
+    assert_eq!(outbox.due(42_999), None);
+    assert_eq!(outbox.next_due(), Some(43_000));
+    assert_eq!(outbox.status().pending, 1);
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn retries_never_log_twice() {
+    let server = MockServer::start().await;
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    let id = outbox.push(segment()).unwrap();
+
+    // The first attempt made it to Jira, but the response was lost on the way back
+    Mock::given(method("POST"))
+      .respond_with(ResponseTemplate::new(502))
+      .up_to_n_times(1)
+      .expect(1)
+      .mount(&server)
+      .await;
+    let entry = outbox.due(now()).unwrap();
+    assert!(matches!(
+      outbox.deliver(&client(&server), &entry, 0).await,
+      Delivery::Retrying { at: 5_000, .. }
+    ));
+
+    Mock::given(method("GET"))
+      .and(path("/rest/api/3/issue/JRD-12/worklog"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "worklogs": [{
+          "id": "100028",
+          "timeSpentSeconds": 1500,
+          "properties": [{ "key": "jiradoro", "value": { "id": id } }],
+        }],
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+    let entry = outbox.due(5_000).unwrap();
+    assert_eq!(
+      outbox.deliver(&client(&server), &entry, 5_000).await,
+      Delivery::Delivered(Worklog {
+        id: "100028".to_string(),
+        issue_key: "JRD-12".to_string(),
+        time_spent_seconds: 1500,
+      })
+    );
+    assert!(outbox.entries().is_empty());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn requests_cut_off_by_closing_are_not_sent_twice() {
+    let server = MockServer::start().await;
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    let id = outbox.push(segment()).unwrap();
+
+    // The app is closed while the first request is still on its way to Jira
+    Mock::given(method("POST"))
+      .respond_with(created().set_delay(Duration::from_secs(30)))
+      .up_to_n_times(1)
+      .mount(&server)
+      .await;
+    let entry = outbox.due(now()).unwrap();
+    let client = client(&server);
+    let sending = outbox.deliver(&client, &entry, now());
+    assert!(tokio::time::timeout(Duration::from_millis(200), sending)
+      .await
+      .is_err());
+    drop(outbox);
+
+    // Jira recorded it, so opening the outbox again finds it rather than posting another
+    let reopened = Outbox::open(&file).unwrap();
+    let entry = reopened.due(now()).unwrap();
+    assert_eq!((entry.attempts, entry.sent), (0, true));
+    Mock::given(method("GET"))
+      .and(path("/rest/api/3/issue/JRD-12/worklog"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "worklogs": [{
+          "id": "100028",
+          "timeSpentSeconds": 1500,
+          "properties": [{ "key": "jiradoro", "value": { "id": id } }],
+        }],
+      })))
+      .expect(1)
+      .mount(&server)
+      .await;
+    assert!(matches!(
+      reopened.deliver(&client, &entry, now()).await,
+      Delivery::Delivered(_)
+    ));
+    assert!(reopened.entries().is_empty());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn refused_entries_fail_until_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .respond_with(ResponseTemplate::new(404).set_body_string("Issue does not exist"))
+      .mount(&server)
+      .await;
+
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    outbox.push(segment()).unwrap();
+
+    let entry = outbox.due(now()).unwrap();
+    assert!(matches!(
+      outbox.deliver(&client(&server), &entry, now()).await,
+      Delivery::Failed(JiraError::Status { code: 404, .. })
+    ));
+    assert_eq!(outbox.due(u64::MAX), None);
+    assert_eq!(outbox.status().failed, 1);
+
+    outbox.retry();
+    assert_eq!(outbox.status().failed, 0);
+    assert!(outbox.due(now()).is_some());
+    fs::remove_file(file).unwrap();
+  }
+
+  #[tokio::test]
+  async fn forbidden_entries_fail_rather_than_retry() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+      .respond_with(ResponseTemplate::new(403).set_body_string("No permission to log work"))
+      .mount(&server)
+      .await;
+
+    let file = scratch_file();
+    let outbox = Outbox::open(&file).unwrap();
+    outbox.push(segment()).unwrap();
+
+    let entry = outbox.due(now()).unwrap();
+    assert!(matches!(
+      outbox.deliver(&client(&server), &entry, now()).await,
+      Delivery::Failed(JiraError::Status { code: 403, .. })
+    ));
+    assert_eq!(outbox.due(u64::MAX), None);
+    assert_eq!(outbox.status().failed, 1);
+    fs::remove_file(file).unwrap();
+  }
+}
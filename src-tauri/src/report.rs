@@ -0,0 +1,269 @@
+//! Works out productivity reports from the segments in the history. Days and weeks are those of
+//! the given time zone, so a late session counts towards the day it was worked on locally.
+
+use chrono::{Datelike, NaiveDate, TimeZone};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use jiradoro_common::prelude::*;
+
+/// The local day a time falls on
+fn day<Tz: TimeZone>(millis: u64, tz: &Tz) -> Option<NaiveDate> {
+  let time = tz.timestamp_millis_opt(millis as i64).earliest()?;
+  Some(time.date_naive())
+}
+
+fn add(totals: &mut BTreeMap<String, PeriodTotal>, period: String, focused: u64, completed: bool) {
+  let total = totals.entry(period.clone()).or_insert_with(|| PeriodTotal {
+    period,
+    ..PeriodTotal::default()
+  });
+  total.focused += focused;
+  match completed {
+    true => total.completed += 1,
+    false => total.abandoned += 1,
+  }
+}
+
+/// The totals with the most focused first
+fn ranked(totals: HashMap<Option<String>, KeyTotal>) -> Vec<KeyTotal> {
+  let mut totals: Vec<KeyTotal> = totals.into_values().collect();
+  totals.sort_by(|a, b| b.focused.cmp(&a.focused).then_with(|| a.key.cmp(&b.key)));
+  totals
+}
+
+/// Report on the segments, which are expected to be the ones started between `from` and `to`.
+/// Sessions still going are left out until they end.
+pub fn build<Tz: TimeZone>(segments: &[Segment], from: u64, to: u64, tz: &Tz) -> Report {
+  let mut segments: Vec<&Segment> = segments.iter().collect();
+  segments.sort_by_key(|segment| segment.started);
+
+  let mut report = Report {
+    from,
+    to,
+    ..Report::default()
+  };
+  let mut days = BTreeMap::new();
+  let mut weeks = BTreeMap::new();
+  let mut issues = HashMap::new();
+  let mut projects = HashMap::new();
+
+  for (index, segment) in segments.iter().enumerate() {
+    if segment.kind != SegmentKind::Work {
+      if segment.completed {
+        report.breaks_completed += 1;
+      }
+      continue;
+    }
+    let (focused, day) = match (segment.focused(), day(segment.started, tz)) {
+      (Some(focused), Some(day)) => (focused, day),
+      _ => continue,
+    };
+
+    report.focused += focused;
+    report.interruptions += segment.pauses.len() as u32;
+    report.paused += segment
+      .pauses
+      .iter()
+      .map(|pause| pause.ended.saturating_sub(pause.started) / 1_000)
+      .sum::<u64>();
+    if segment.completed {
+      report.completed += 1;
+      report.breaks_due += 1;
+      let next = segments.get(index + 1).map(|next| next.kind);
+      if matches!(next, Some(SegmentKind::Break | SegmentKind::LongBreak)) {
+        report.breaks_taken += 1;
+      }
+    } else {
+      report.abandoned += 1;
+    }
+
+    let week = day.iso_week();
+    add(
+      &mut days,
+      day.format("%Y-%m-%d").to_string(),
+      focused,
+      segment.completed,
+    );
+    let week = format!("{}-W{:02}", week.year(), week.week());
+    add(&mut weeks, week, focused, segment.completed);
+
+    // A session that switched issues counts towards each of them for the time spent on it, but only
+    // once towards a project however many of its issues it went between
+    let ended = segment.ended.unwrap_or(segment.started);
+    let mut counted: [BTreeSet<Option<String>>; 2] = Default::default();
+    for time in segment.issue_times(ended) {
+      let project = time
+        .issue_key
+        .as_deref()
+        .map(project_key)
+        .map(str::to_string);
+      let keyed = [(&mut issues, time.issue_key), (&mut projects, project)];
+      for ((totals, key), counted) in keyed.into_iter().zip(&mut counted) {
+        let total = totals.entry(key.clone()).or_insert_with(|| KeyTotal {
+          key: key.clone(),
+          ..KeyTotal::default()
+        });
+        total.focused += time.focused;
+        if counted.insert(key) {
+          total.sessions += 1;
+        }
+      }
+    }
+  }
+
+  report.days = days.into_values().collect();
+  report.weeks = weeks.into_values().collect();
+  report.issues = ranked(issues);
+  report.projects = ranked(projects);
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{FixedOffset, Utc};
+
+  const MINUTE: u64 = 60 * 1_000;
+
+  /// Monday the 19th of August 2024, at midnight UTC
+  const MONDAY: u64 = 1_724_025_600_000;
+
+  fn segment(kind: SegmentKind, started: u64, minutes: u64, issue_key: Option<&str>) -> Segment {
+    let mut segment = Segment::new(kind, started, issue_key.map(str::to_string));
+    segment.ended = Some(started + minutes * MINUTE);
+    segment.completed = true;
+    segment
+  }
+
+  fn session(started: u64, issue_key: &str) -> Segment {
+    segment(SegmentKind::Work, started, 25, Some(issue_key))
+  }
+
+  #[test]
+  fn totals_focused_time_by_day_issue_and_project() {
+    let mut paused = session(MONDAY + 9 * 60 * MINUTE, "JRD-1");
+    paused.pauses.push(Pause {
+      started: paused.started + MINUTE,
+      ended: paused.started + 6 * MINUTE,
+    });
+    paused.ended = Some(paused.started + 30 * MINUTE);
+    let segments = vec![
+      paused,
+      session(MONDAY + 10 * 60 * MINUTE, "JRD-2"),
+      session(MONDAY + 24 * 60 * MINUTE, "OPS-7"),
+      session(MONDAY + 7 * 24 * 60 * MINUTE, "JRD-1"),
+    ];
+    let report = build(&segments, MONDAY, MONDAY + 14 * 24 * 60 * MINUTE, &Utc);
+
+    assert_eq!(report.focused, 4 * 25 * 60);
+    assert_eq!((report.interruptions, report.paused), (1, 5 * 60));
+    let days: Vec<(&str, u64)> = report
+      .days
+      .iter()
+      .map(|day| (day.period.as_str(), day.focused / 60))
+      .collect();
+    assert_eq!(
+      days,
+      vec![("2024-08-19", 50), ("2024-08-20", 25), ("2024-08-26", 25)]
+    );
+    let weeks: Vec<&str> = report
+      .weeks
+      .iter()
+      .map(|week| week.period.as_str())
+      .collect();
+    assert_eq!(weeks, vec!["2024-W34", "2024-W35"]);
+
+    let issues: Vec<(Option<&str>, u64)> = report
+      .issues
+      .iter()
+      .map(|issue| (issue.key.as_deref(), issue.focused / 60))
+      .collect();
+    assert_eq!(
+      issues,
+      vec![
+        (Some("JRD-1"), 50),
+        (Some("JRD-2"), 25),
+        (Some("OPS-7"), 25)
+      ]
+    );
+    let projects: Vec<(Option<&str>, u32)> = report
+      .projects
+      .iter()
+      .map(|project| (project.key.as_deref(), project.sessions))
+      .collect();
+    assert_eq!(projects, vec![(Some("JRD"), 3), (Some("OPS"), 1)]);
+  }
+
+  #[test]
+  fn splits_sessions_that_switched_issues() {
+    let mut switched = session(MONDAY, "OPS-7");
+    switched.switches.push(IssueSwitch {
+      at: MONDAY + 10 * MINUTE,
+      from: Some("JRD-1".to_string()),
+    });
+    // Going between two issues of the same project is still one session for the project
+    let mut within = session(MONDAY + 60 * MINUTE, "JRD-2");
+    within.switches.push(IssueSwitch {
+      at: MONDAY + 65 * MINUTE,
+      from: Some("JRD-1".to_string()),
+    });
+    let segments = vec![switched, session(MONDAY + 30 * MINUTE, "JRD-1"), within];
+    let report = build(&segments, MONDAY, MONDAY + 24 * 60 * MINUTE, &Utc);
+
+    let totals = |totals: &[KeyTotal]| -> Vec<(Option<String>, u64, u32)> {
+      totals
+        .iter()
+        .map(|total| (total.key.clone(), total.focused / 60, total.sessions))
+        .collect()
+    };
+    let key = |key: &str| Some(key.to_string());
+    assert_eq!(
+      totals(&report.issues),
+      vec![
+        (key("JRD-1"), 40, 3),
+        (key("JRD-2"), 20, 1),
+        (key("OPS-7"), 15, 1)
+      ]
+    );
+    assert_eq!(
+      totals(&report.projects),
+      vec![(key("JRD"), 60, 3), (key("OPS"), 15, 1)]
+    );
+    assert_eq!(report.focused, 75 * 60);
+  }
+
+  #[test]
+  fn groups_days_in_the_local_time_zone() {
+    // Half past eleven at night in UTC is already the next day further east
+    let segments = vec![session(MONDAY + (23 * 60 + 30) * MINUTE, "JRD-1")];
+    let east = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+    let report = build(&segments, MONDAY, MONDAY + 48 * 60 * MINUTE, &east);
+    assert_eq!(report.days[0].period, "2024-08-20");
+  }
+
+  #[test]
+  fn counts_abandoned_sessions_and_missed_breaks() {
+    let mut abandoned = session(MONDAY + 60 * MINUTE, "JRD-1");
+    abandoned.completed = false;
+    let mut cut_short = segment(SegmentKind::Break, MONDAY + 25 * MINUTE, 2, None);
+    cut_short.completed = false;
+    let mut going = Segment::new(SegmentKind::Work, MONDAY + 120 * MINUTE, None);
+    going.ended = None;
+    let segments = vec![
+      session(MONDAY, "JRD-1"),
+      cut_short,
+      abandoned,
+      session(MONDAY + 90 * MINUTE, "JRD-1"),
+      going,
+      segment(SegmentKind::LongBreak, MONDAY + 150 * MINUTE, 15, None),
+    ];
+    let report = build(&segments, MONDAY, MONDAY + 24 * 60 * MINUTE, &Utc);
+
+    assert_eq!((report.completed, report.abandoned), (2, 1));
+    // The second session went straight into another one rather than a break
+    assert_eq!((report.breaks_due, report.breaks_taken), (2, 1));
+    assert_eq!(report.breaks_completed, 1);
+    assert_eq!(report.break_adherence(), Some(50));
+    assert_eq!(report.issues.len(), 1);
+  }
+}
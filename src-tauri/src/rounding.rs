@@ -0,0 +1,217 @@
+//! Rounds finished sessions on their way to the outbox. The rule comes from the settings for the
+//! issue's project, and the history says what was already logged to the project that day and which
+//! short sessions are waiting to be merged.
+
+use chrono::{NaiveTime, TimeZone};
+
+use crate::history::History;
+use jiradoro_common::prelude::*;
+
+/// The start of the local day the time falls on and the start of the day after, in milliseconds
+fn day<Tz: TimeZone>(at: u64, tz: &Tz) -> (u64, u64) {
+  let start = tz
+    .timestamp_millis_opt(at as i64)
+    .earliest()
+    .map(|time| time.date_naive())
+    .and_then(|date| {
+      let start = |date: chrono::NaiveDate| {
+        let midnight = date.and_time(NaiveTime::MIN);
+        let local = tz.from_local_datetime(&midnight).earliest()?;
+        Some(local.timestamp_millis() as u64)
+      };
+      Some((start(date)?, start(date.succ_opt()?)?))
+    });
+  // A day that doesn't start at midnight is rare enough to fall back on a plain 24 hours
+  start.unwrap_or((at, at + 24 * 60 * 60 * 1_000))
+}
+
+/// What has been logged to the issue's project on the day of the time, and held on the issue
+pub fn ledger<Tz: TimeZone>(
+  history: &History,
+  issue_key: &str,
+  at: u64,
+  tz: &Tz,
+) -> rusqlite::Result<Ledger> {
+  let (from, to) = day(at, tz);
+  history.ledger(issue_key, from, to)
+}
+
+/// What the rule for the issue's project makes of the finished session
+pub fn outcome<Tz: TimeZone>(
+  history: &History,
+  rounding: &RoundingSettings,
+  work: &WorkSegment,
+  tz: &Tz,
+) -> rusqlite::Result<Outcome> {
+  let ledger = ledger(history, &work.issue_key, work.started, tz)?;
+  Ok(rounding.rule(&work.issue_key).apply(work.duration, &ledger))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{FixedOffset, Utc};
+
+  const MINUTE: u64 = 60 * 1_000;
+
+  /// Monday the 19th of August 2024, at midnight UTC
+  const MONDAY: u64 = 1_724_025_600_000;
+
+  fn history() -> History {
+    History::open(":memory:").unwrap()
+  }
+
+  /// Run a session through the rounding the way the server does, noting the outcome
+  fn record(
+    history: &History,
+    rounding: &RoundingSettings,
+    started: u64,
+    minutes: u64,
+    issue_key: &str,
+  ) -> Outcome {
+    let mut segment = Segment::new(SegmentKind::Work, started, Some(issue_key.to_string()));
+    segment.ended = Some(started + minutes * MINUTE);
+    segment.completed = true;
+    history.save(&segment).unwrap();
+
+    let work = WorkSegment {
+      issue_key: issue_key.to_string(),
+      started,
+      duration: minutes * 60,
+      comment: None,
+      segment: Some(segment.id),
+    };
+    let outcome = outcome(history, rounding, &work, &Utc).unwrap();
+    history.rounded(&work, outcome).unwrap();
+    outcome
+  }
+
+  #[test]
+  fn rounds_to_the_multiple() {
+    let rule = |mode, minutes| RoundingRule {
+      mode,
+      minutes,
+      ..RoundingRule::default()
+    };
+    let seven = 7 * 60 + 20;
+    assert_eq!(rule(RoundingMode::Off, 15).round(seven), seven);
+    assert_eq!(rule(RoundingMode::Nearest, 5).round(seven), 5 * 60);
+    assert_eq!(rule(RoundingMode::Nearest, 15).round(seven + 40), 15 * 60);
+    assert_eq!(rule(RoundingMode::Up, 1).round(seven), 8 * 60);
+    assert_eq!(rule(RoundingMode::Up, 30).round(seven), 30 * 60);
+    // Even a few minutes are logged as something
+    assert_eq!(rule(RoundingMode::Nearest, 30).round(seven), 30 * 60);
+    assert_eq!(rule(RoundingMode::Up, 15).round(0), 0);
+  }
+
+  #[test]
+  fn uses_the_rule_for_the_issues_project() {
+    let mut rounding = RoundingSettings::default();
+    rounding.default.mode = RoundingMode::Up;
+    rounding.projects.insert(
+      "OPS".to_string(),
+      RoundingRule {
+        mode: RoundingMode::Nearest,
+        minutes: 30,
+        ..RoundingRule::default()
+      },
+    );
+
+    let history = history();
+    assert_eq!(
+      record(&history, &rounding, MONDAY, 20, "JRD-1"),
+      Outcome::Log(30 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY, 20, "OPS-7"),
+      Outcome::Log(30 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY, 50, "OPS-7"),
+      Outcome::Log(60 * 60)
+    );
+  }
+
+  #[test]
+  fn discards_or_merges_short_sessions() {
+    let mut rounding = RoundingSettings::default();
+    rounding.default.minimum = 10;
+    rounding.default.short = ShortSessions::Discard;
+    let history = history();
+    assert_eq!(
+      record(&history, &rounding, MONDAY, 4, "JRD-1"),
+      Outcome::Short
+    );
+
+    rounding.default.short = ShortSessions::Merge;
+    assert_eq!(
+      record(&history, &rounding, MONDAY, 4, "JRD-1"),
+      Outcome::Hold(4 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 5 * MINUTE, 3, "JRD-1"),
+      Outcome::Hold(3 * 60)
+    );
+    // Held time only merges into sessions on the same issue
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 10 * MINUTE, 25, "JRD-2"),
+      Outcome::Log(25 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 40 * MINUTE, 5, "JRD-1"),
+      Outcome::Log(12 * 60)
+    );
+    assert_eq!(
+      history
+        .ledger("JRD-1", 0, MONDAY + 24 * 60 * MINUTE)
+        .unwrap()
+        .held,
+      0
+    );
+  }
+
+  #[test]
+  fn caps_the_time_logged_to_a_project_each_day() {
+    let mut rounding = RoundingSettings::default();
+    rounding.default.daily_cap = Some(60);
+    let history = history();
+    let hour = 60 * MINUTE;
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 9 * hour, 25, "JRD-1"),
+      Outcome::Log(25 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 10 * hour, 25, "JRD-2"),
+      Outcome::Log(25 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 11 * hour, 25, "JRD-1"),
+      Outcome::Log(10 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 12 * hour, 25, "JRD-1"),
+      Outcome::OverCap
+    );
+    // Other projects and other days have their own allowance
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 13 * hour, 25, "OPS-7"),
+      Outcome::Log(25 * 60)
+    );
+    assert_eq!(
+      record(&history, &rounding, MONDAY + 33 * hour, 25, "JRD-1"),
+      Outcome::Log(25 * 60)
+    );
+  }
+
+  #[test]
+  fn days_follow_the_time_zone() {
+    let east = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+    let hour = 60 * MINUTE;
+    // Eleven at night in UTC is one in the morning on the Tuesday two hours east
+    assert_eq!(
+      day(MONDAY + 23 * hour, &east),
+      (MONDAY + 22 * hour, MONDAY + 46 * hour)
+    );
+    assert_eq!(day(MONDAY + 23 * hour, &Utc), (MONDAY, MONDAY + 24 * hour));
+  }
+}
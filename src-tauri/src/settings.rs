@@ -0,0 +1,227 @@
+//! The user's settings, kept as JSON in the app's config directory.
+//!
+//! The file records the version of its layout. Older files are brought up to date by running them
+//! through each migration in turn before they are read, so a change to the layout only needs a new
+//! entry in `MIGRATIONS`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::outbox::now;
+use jiradoro_common::prelude::*;
+
+/// Brings a file from one version of the layout to the next
+type Migration = fn(Value) -> Value;
+
+/// The migration at each index takes a file from version `index + 1` to `index + 2`
+const MIGRATIONS: &[Migration] = &[rounding_per_project];
+
+/// Version 1 had a single rounding rule, which becomes the default for every project
+fn rounding_per_project(mut file: Value) -> Value {
+  let settings = &mut file["settings"];
+  if let Some(rule) = settings.get_mut("rounding").map(Value::take) {
+    settings["rounding"] = serde_json::json!({ "default": rule });
+  }
+  file
+}
+
+/// The version of the file layout, which goes up with every migration
+const FILE_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+#[derive(Serialize, Deserialize)]
+struct SettingsFile {
+  version: u32,
+  settings: Settings,
+}
+
+/// Bring a file written with any earlier layout up to the current one
+fn migrate(mut file: Value, migrations: &[Migration]) -> Result<Value, String> {
+  let current = migrations.len() as u32 + 1;
+  loop {
+    let version = match file.get("version").and_then(Value::as_u64) {
+      Some(version) => version as u32,
+      None => return Err("the file has no version".to_string()),
+    };
+    if version == current {
+      return Ok(file);
+    }
+    let migration = match version
+      .checked_sub(1)
+      .and_then(|index| migrations.get(index as usize))
+    {
+      Some(migration) => migration,
+      None => return Err(format!("version {} is not supported", version)),
+    };
+    info!("Migrating the settings from version {}", version);
+    file = migration(file);
+    file["version"] = Value::from(version + 1);
+  }
+}
+
+/// Read the settings out of a file's contents, whatever version of the layout it was written in
+fn parse(contents: &str, migrations: &[Migration]) -> Result<Settings, String> {
+  let file = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+  let file = migrate(file, migrations)?;
+  let file: SettingsFile = serde_json::from_value(file).map_err(|err| err.to_string())?;
+  Ok(file.settings)
+}
+
+pub struct SettingsStore {
+  path: PathBuf,
+  settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+  /// Open the settings stored at the path, starting from the defaults if there aren't any yet. A
+  /// file that can't be read is set aside rather than overwritten so it can be fixed by hand.
+  pub fn open(path: impl AsRef<Path>) -> io::Result<SettingsStore> {
+    let path = path.as_ref().to_path_buf();
+    let settings = match fs::read_to_string(&path) {
+      Ok(contents) => match parse(&contents, MIGRATIONS) {
+        Ok(settings) => settings,
+        Err(err) => {
+          error!("Could not read the settings at {}: {}", path.display(), err);
+          let aside = path.with_extension(format!("unreadable-{}", now()));
+          warn!("Moving the settings to {}", aside.display());
+          fs::rename(&path, aside)?;
+          Settings::default()
+        }
+      },
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Settings::default(),
+      Err(err) => return Err(err),
+    };
+    info!("Opened the settings at {}", path.display());
+    Ok(SettingsStore {
+      path,
+      settings: Mutex::new(settings),
+    })
+  }
+
+  /// Write the settings out, replacing the file only once they are fully written
+  fn save(&self, settings: &Settings) -> io::Result<()> {
+    let file = SettingsFile {
+      version: FILE_VERSION,
+      settings: settings.clone(),
+    };
+    let contents = serde_json::to_string_pretty(&file)?;
+    let partial = self.path.with_extension("partial");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, &self.path)
+  }
+
+  pub fn get(&self) -> Settings {
+    self.settings.lock().unwrap().clone()
+  }
+
+  /// Change the settings, keeping them unchanged unless the change could be saved
+  pub fn update(&self, change: impl FnOnce(&mut Settings)) -> io::Result<Settings> {
+    let mut settings = self.settings.lock().unwrap();
+    let mut changed = settings.clone();
+    change(&mut changed);
+    self.save(&changed)?;
+    *settings = changed;
+    Ok(settings.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use uuid::Uuid;
+
+  /// A path for a settings file that no other test is using
+  fn scratch_file() -> PathBuf {
+    std::env::temp_dir().join(format!("jiradoro-settings-{}.json", Uuid::new_v4()))
+  }
+
+  #[test]
+  fn starts_from_the_defaults() {
+    let file = scratch_file();
+    let store = SettingsStore::open(&file).unwrap();
+    assert_eq!(store.get(), Settings::default());
+    assert!(!file.exists());
+  }
+
+  #[test]
+  fn settings_survive_a_restart() {
+    let file = scratch_file();
+    let store = SettingsStore::open(&file).unwrap();
+    let saved = store
+      .update(|settings| {
+        settings.timer.session_length = 50 * 60;
+        settings.jira_site = Some("https://example.atlassian.net".to_string());
+      })
+      .unwrap();
+
+    assert_eq!(SettingsStore::open(&file).unwrap().get(), saved);
+    fs::remove_file(file).unwrap();
+  }
+
+  #[test]
+  fn fills_in_settings_the_file_is_missing() {
+    let contents = json!({
+      "version": FILE_VERSION,
+      "settings": { "timer": { "long_break_every": 3 } },
+    });
+    let settings = parse(&contents.to_string(), MIGRATIONS).unwrap();
+
+    let mut expected = Settings::default();
+    expected.timer.long_break_every = 3;
+    assert_eq!(settings, expected);
+  }
+
+  #[test]
+  fn migrates_older_files() {
+    // A made up first version which kept the session length in minutes at the top level
+    fn nest_the_timer(mut file: Value) -> Value {
+      let minutes = file["session_minutes"].take().as_u64().unwrap();
+      json!({
+        "version": file["version"],
+        "settings": { "timer": { "session_length": minutes * 60 } },
+      })
+    }
+
+    let contents = json!({ "version": 1, "session_minutes": 50 });
+    let settings = parse(&contents.to_string(), &[nest_the_timer]).unwrap();
+    assert_eq!(settings.timer.session_length, 50 * 60);
+  }
+
+  #[test]
+  fn keeps_the_rounding_from_the_first_version() {
+    let contents = json!({
+      "version": 1,
+      "settings": { "rounding": { "mode": "Up", "minutes": 30 } },
+    });
+    let settings = parse(&contents.to_string(), MIGRATIONS).unwrap();
+
+    let rule = &settings.rounding.default;
+    assert_eq!((rule.mode, rule.minutes), (RoundingMode::Up, 30));
+    assert!(settings.rounding.projects.is_empty());
+  }
+
+  #[test]
+  fn sets_aside_files_it_cannot_read() {
+    let file = scratch_file();
+    let newer = json!({ "version": FILE_VERSION + 1, "settings": {} });
+    fs::write(&file, newer.to_string()).unwrap();
+
+    let store = SettingsStore::open(&file).unwrap();
+    assert_eq!(store.get(), Settings::default());
+    assert!(!file.exists());
+
+    let prefix = file.file_stem().unwrap().to_string_lossy().to_string();
+    let aside: Vec<_> = fs::read_dir(std::env::temp_dir())
+      .unwrap()
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+      .collect();
+    assert_eq!(aside.len(), 1);
+    fs::remove_file(aside[0].path()).unwrap();
+  }
+}
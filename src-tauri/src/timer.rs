@@ -0,0 +1,662 @@
+//! The Pomodoro timer. Time is worked out from wall clock timestamps rather than counted in ticks,
+//! so it stays right when the app is busy or asleep, and lives on the server so reloading the
+//! window doesn't lose it. Sessions and breaks follow the classic cycle, with a long break once
+//! every few sessions, and can move on to the next phase by themselves.
+//!
+//! The `RunTimer` process sends a snapshot of the timer to the frontend every second while it is
+//! counting, and straight away whenever a command changes it. Every session and break is written to
+//! the history as it starts, pauses and ends, and the moment each one runs out is announced to
+//! whoever subscribed to the timer's boundaries.
+
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use tracing::{error, info};
+
+use crate::history::History;
+use crate::longrunner::prelude::*;
+use crate::outbox::now;
+use jiradoro_common::prelude::*;
+
+/// A session or break running its full length
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Boundary {
+  SessionFinished,
+  BreakFinished,
+}
+
+/// The state machine behind the timer, with every change stamped with the time it happened
+#[derive(PartialEq, Clone, Debug)]
+struct Clock {
+  state: TimerState,
+  /// Seconds the session or break is planned to last
+  length: u64,
+  /// Milliseconds counted before the timer was last started
+  banked: u64,
+  /// When the timer was last started, if it is counting
+  resumed: Option<u64>,
+  /// When the timer was last paused, if it is paused part way through a session
+  paused: Option<u64>,
+  /// The session or break as it is kept in the history, once it has started
+  segment: Option<Segment>,
+  /// The issue new sessions are worked against
+  issue: Option<String>,
+  settings: TimerSettings,
+  /// Sessions completed since the last long break
+  cycle: u32,
+  /// Sessions completed since the app started
+  sessions: u32,
+  /// Whether the current session or break has already been counted as running its full length
+  counted: bool,
+  /// The last session to run its full length, set once it has ended along with any overtime
+  completed: Option<CompletedSession>,
+  /// Segments changed since the history was last brought up to date
+  changes: Vec<Segment>,
+  /// Boundaries reached since they were last announced
+  boundaries: Vec<Boundary>,
+}
+
+impl Clock {
+  fn new(settings: TimerSettings) -> Clock {
+    Clock {
+      state: TimerState::Paused,
+      length: settings.session_length,
+      banked: 0,
+      resumed: None,
+      paused: None,
+      segment: None,
+      issue: None,
+      settings,
+      cycle: 0,
+      sessions: 0,
+      counted: false,
+      completed: None,
+      changes: Vec::new(),
+      boundaries: Vec::new(),
+    }
+  }
+
+  /// Milliseconds counted as of now
+  fn elapsed(&self, now: u64) -> u64 {
+    let running = self
+      .resumed
+      .map(|resumed| now.saturating_sub(resumed))
+      .unwrap_or_default();
+    self.banked + running
+  }
+
+  /// Note that the current segment has changed, so the history gets the change
+  fn record(&mut self) {
+    if let Some(segment) = &self.segment {
+      self.changes.push(segment.clone());
+    }
+  }
+
+  /// Bring the current session or break to an end, if one has started
+  fn end(&mut self, now: u64) {
+    let completed = self.elapsed(now) >= self.length * 1_000;
+    if let Some(segment) = &mut self.segment {
+      if let Some(paused) = self.paused.take() {
+        segment.pauses.push(Pause {
+          started: paused,
+          ended: now,
+        });
+      }
+      segment.ended = Some(now);
+      segment.completed = completed;
+      if completed && segment.kind == SegmentKind::Work {
+        self.completed = Some(CompletedSession {
+          segment: segment.id,
+          started: segment.started,
+          ended: now,
+          length: self.length,
+          focused: segment.focused_until(now),
+          pauses: segment.pauses.clone(),
+          issues: segment.issue_times(now),
+        });
+      }
+    }
+    self.record();
+    self.segment = None;
+  }
+
+  /// Start a new segment in the history
+  fn open_segment(&mut self, kind: SegmentKind, now: u64) {
+    let issue = match kind {
+      SegmentKind::Work => self.issue.clone(),
+      SegmentKind::Break | SegmentKind::LongBreak => None,
+    };
+    self.segment = Some(Segment::new(kind, now, issue));
+    self.record();
+  }
+
+  /// Start counting a fresh session or break, ending the one before it
+  fn begin(&mut self, state: TimerState, now: u64) {
+    self.end(now);
+    self.length = match state {
+      TimerState::Break => self.settings.short_break,
+      TimerState::LongBreak => self.settings.long_break,
+      TimerState::Running | TimerState::Paused => self.settings.session_length,
+    };
+    if state == TimerState::LongBreak {
+      self.cycle = 0;
+    }
+    self.state = state;
+    self.banked = 0;
+    self.resumed = (state != TimerState::Paused).then_some(now);
+    self.counted = false;
+    match state {
+      TimerState::Running => self.open_segment(SegmentKind::Work, now),
+      TimerState::Break => self.open_segment(SegmentKind::Break, now),
+      TimerState::LongBreak => self.open_segment(SegmentKind::LongBreak, now),
+      TimerState::Paused => (),
+    }
+  }
+
+  /// The break that follows the current session
+  fn next_break(&self) -> TimerState {
+    if self.cycle >= self.settings.long_break_every.max(1) {
+      TimerState::LongBreak
+    } else {
+      TimerState::Break
+    }
+  }
+
+  /// Catch up with everything that happened by now: count the session once it has run its full
+  /// length, and move on to whatever comes next when that is meant to happen on its own. Otherwise
+  /// the session keeps running into overtime until it is ended.
+  fn advance(&mut self, now: u64) {
+    while self.resumed.is_some() {
+      let elapsed = self.elapsed(now);
+      let length = self.length * 1_000;
+      if elapsed < length {
+        return;
+      }
+
+      // The moment this one ran out
+      let ended = now - (elapsed - length);
+      if !self.counted {
+        self.counted = true;
+        if self.state == TimerState::Running {
+          self.cycle += 1;
+          self.sessions += 1;
+          self.boundaries.push(Boundary::SessionFinished);
+        } else {
+          self.boundaries.push(Boundary::BreakFinished);
+        }
+      }
+      if !self.settings.auto_start {
+        return;
+      }
+
+      // Whatever comes next started the moment this one ran out
+      match self.state {
+        TimerState::Running => self.begin(self.next_break(), ended),
+        _ => self.begin(TimerState::Running, ended),
+      }
+    }
+  }
+
+  fn apply(&mut self, command: TimerCommand, now: u64) {
+    self.advance(now);
+    match (command, self.state) {
+      (TimerCommand::Start, TimerState::Paused) => {
+        self.state = TimerState::Running;
+        self.resumed = Some(now);
+        match (&mut self.segment, self.paused.take()) {
+          (Some(segment), Some(paused)) => {
+            segment.pauses.push(Pause {
+              started: paused,
+              ended: now,
+            });
+            self.record();
+          }
+          (Some(_), None) => (),
+          (None, _) => self.open_segment(SegmentKind::Work, now),
+        }
+      }
+      (TimerCommand::Start, TimerState::Break | TimerState::LongBreak) => {
+        self.begin(TimerState::Running, now)
+      }
+      (TimerCommand::Pause, TimerState::Running) => {
+        self.banked = self.elapsed(now);
+        self.resumed = None;
+        self.paused = Some(now);
+        self.state = TimerState::Paused;
+      }
+      (TimerCommand::Break, _) => self.begin(self.next_break(), now),
+      (TimerCommand::Continue, _) => self.begin(TimerState::Running, now),
+      (TimerCommand::Reset, _) => self.begin(TimerState::Paused, now),
+      (TimerCommand::SetLength(length), _) => self.length = length,
+      (TimerCommand::Configure(settings), _) => {
+        self.settings = settings;
+        // A session that hasn't started yet can still take on the new length
+        if self.segment.is_none() {
+          self.length = self.settings.session_length;
+        }
+      }
+      (TimerCommand::SetIssue(issue), _) => {
+        self.issue = issue;
+        if let Some(segment) = &mut self.segment {
+          if segment.kind == SegmentKind::Work && segment.issue_key != self.issue {
+            // The time already spent stays with the issue it was spent on
+            let from = std::mem::replace(&mut segment.issue_key, self.issue.clone());
+            if now > segment.started {
+              segment.switches.push(IssueSwitch { at: now, from });
+            }
+            self.record();
+          }
+        }
+      }
+      (command, state) => info!("Ignoring {:?} while {:?}", command, state),
+    }
+  }
+
+  fn snapshot(&mut self, now: u64) -> TimerSnapshot {
+    self.advance(now);
+    TimerSnapshot {
+      state: self.state,
+      elapsed: self.elapsed(now) / 1_000,
+      length: self.length,
+      started: self.segment.as_ref().map(|segment| segment.started),
+      cycle: self.cycle,
+      sessions: self.sessions,
+      completed: self.completed.clone(),
+      settings: self.settings.clone(),
+      issue: self.issue.clone(),
+    }
+  }
+}
+
+/// The timer shared between the server, which changes it, and the process sending it out
+pub struct Timer {
+  clock: Mutex<Clock>,
+  changed: Notify,
+  history: Arc<History>,
+  boundaries: broadcast::Sender<Boundary>,
+}
+
+impl Timer {
+  pub fn new(settings: TimerSettings, history: Arc<History>) -> Timer {
+    Timer {
+      clock: Mutex::new(Clock::new(settings)),
+      changed: Notify::new(),
+      history,
+      boundaries: broadcast::channel(16).0,
+    }
+  }
+
+  /// Hear about every session and break that runs its full length from now on
+  pub fn boundaries(&self) -> broadcast::Receiver<Boundary> {
+    self.boundaries.subscribe()
+  }
+
+  /// Work out the snapshot after an optional change, saving whatever it did to the history
+  fn update(&self, command: Option<TimerCommand>) -> TimerSnapshot {
+    let now = now();
+    let (snapshot, boundaries) = {
+      let mut clock = self.clock.lock().unwrap();
+      if let Some(command) = command {
+        clock.apply(command, now);
+      }
+      let snapshot = clock.snapshot(now);
+      // The changes are saved before the clock is let go, as a later change to the same segment
+      // saved first would otherwise be overwritten by this older one
+      for segment in std::mem::take(&mut clock.changes) {
+        if let Err(err) = self.history.save(&segment) {
+          error!("Could not save {} to the history: {}", segment.id, err);
+        }
+      }
+      (snapshot, std::mem::take(&mut clock.boundaries))
+    };
+    for boundary in boundaries {
+      // Nobody listening is fine, as it only means notifications are turned off
+      let _ = self.boundaries.send(boundary);
+    }
+    snapshot
+  }
+
+  pub fn apply(&self, command: TimerCommand) -> TimerSnapshot {
+    let snapshot = self.update(Some(command));
+    self.changed.notify_one();
+    snapshot
+  }
+
+  pub fn snapshot(&self) -> TimerSnapshot {
+    self.update(None)
+  }
+
+  /// How long until the next whole second is counted, if the timer is counting at all
+  fn next_tick(&self) -> Option<Duration> {
+    let clock = self.clock.lock().unwrap();
+    clock
+      .resumed
+      .map(|_| Duration::from_millis(1_000 - clock.elapsed(now()) % 1_000))
+  }
+}
+
+pub struct RunTimer {
+  pub timer: Arc<Timer>,
+}
+
+#[async_trait]
+impl LongRunnerProcess for RunTimer {
+  type Output = ();
+  type Error = Infallible;
+
+  async fn start(self, tools: ProcessTools) -> Result<(), Infallible> {
+    let mut sent = None;
+    loop {
+      let snapshot = self.timer.snapshot();
+      if sent.as_ref() != Some(&snapshot) {
+        tools.emit(Payload::Timer(snapshot.clone())).await;
+        sent = Some(snapshot);
+      }
+
+      let tick = self.timer.next_tick();
+      tokio::select! {
+        _ = tools.cancellation_token.cancelled() => break,
+        _ = self.timer.changed.notified() => (),
+        _ = tokio::time::sleep(tick.unwrap_or_default()), if tick.is_some() => (),
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MINUTE: u64 = 60 * 1_000;
+
+  fn settings(auto_start: bool) -> TimerSettings {
+    TimerSettings {
+      session_length: 25 * 60,
+      short_break: 5 * 60,
+      long_break: 15 * 60,
+      long_break_every: 2,
+      auto_start,
+    }
+  }
+
+  #[test]
+  fn counts_wall_clock_time_while_running() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::Start, 10_000);
+    assert_eq!(clock.snapshot(12_500).elapsed, 2);
+    assert_eq!(clock.snapshot(12_500).started, Some(10_000));
+    assert_eq!(clock.snapshot(12_500).state, TimerState::Running);
+
+    // However long the process went without looking, the time is still counted
+    let snapshot = clock.snapshot(10_000 + 25 * MINUTE);
+    assert!(snapshot.finished());
+    assert_eq!(snapshot.remaining(), 0);
+    assert_eq!(snapshot.sessions, 1);
+
+    // The session keeps running over its length until it is ended, and is only counted once
+    let snapshot = clock.snapshot(10_000 + 30 * MINUTE);
+    assert_eq!(
+      (snapshot.state, snapshot.sessions),
+      (TimerState::Running, 1)
+    );
+    assert_eq!(clock.boundaries, vec![Boundary::SessionFinished]);
+    assert_eq!(snapshot.completed, None);
+
+    // Carrying on skips the break for a fresh session, and the one before is done with overtime
+    clock.apply(TimerCommand::Continue, 10_000 + 31 * MINUTE);
+    let snapshot = clock.snapshot(10_000 + 31 * MINUTE);
+    assert_eq!((snapshot.state, snapshot.elapsed), (TimerState::Running, 0));
+    assert_eq!(snapshot.started, Some(10_000 + 31 * MINUTE));
+    let completed = snapshot.completed.unwrap();
+    assert_eq!((completed.started, completed.length), (10_000, 25 * 60));
+    assert_eq!(completed.ended, 10_000 + 31 * MINUTE);
+    assert_eq!((completed.focused, completed.overtime()), (31 * 60, 6 * 60));
+  }
+
+  #[test]
+  fn pausing_keeps_the_time_counted() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Pause, 3_000);
+    assert_eq!(clock.snapshot(60_000).elapsed, 3);
+
+    clock.apply(TimerCommand::Start, 60_000);
+    assert_eq!(clock.snapshot(62_000).elapsed, 5);
+    assert_eq!(clock.snapshot(62_000).started, Some(0));
+  }
+
+  #[test]
+  fn breaks_start_counting_from_zero() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Break, 10 * MINUTE);
+
+    let snapshot = clock.snapshot(10 * MINUTE + 1_000);
+    assert_eq!(snapshot.state, TimerState::Break);
+    assert_eq!(snapshot.elapsed, 1);
+    assert_eq!(snapshot.length, 5 * 60);
+    // The session was cut short, so it doesn't count towards a long break
+    assert_eq!((snapshot.cycle, snapshot.sessions), (0, 0));
+
+    // Pausing does nothing during a break, while starting goes back to a new session
+    clock.apply(TimerCommand::Pause, 11 * MINUTE);
+    assert_eq!(clock.snapshot(11 * MINUTE).state, TimerState::Break);
+    clock.apply(TimerCommand::Start, 12 * MINUTE);
+    let snapshot = clock.snapshot(12 * MINUTE);
+    assert_eq!(snapshot.state, TimerState::Running);
+    assert_eq!((snapshot.elapsed, snapshot.length), (0, 25 * 60));
+  }
+
+  #[test]
+  fn takes_a_long_break_every_few_sessions() {
+    let mut clock = Clock::new(settings(false));
+    let mut now = 0;
+    for (index, expected) in [TimerState::Break, TimerState::LongBreak, TimerState::Break]
+      .into_iter()
+      .enumerate()
+    {
+      clock.apply(TimerCommand::Start, now);
+      now += 25 * MINUTE;
+      clock.apply(TimerCommand::Break, now);
+      let snapshot = clock.snapshot(now);
+      assert_eq!(snapshot.state, expected, "after session {}", index + 1);
+      assert_eq!(snapshot.sessions, index as u32 + 1);
+    }
+
+    let snapshot = clock.snapshot(now);
+    assert_eq!((snapshot.cycle, snapshot.length), (1, 5 * 60));
+  }
+
+  #[test]
+  fn moves_on_by_itself_when_auto_starting() {
+    let mut clock = Clock::new(settings(true));
+    clock.apply(TimerCommand::Start, 0);
+
+    // Asleep through a session, a break and most of the next session
+    let snapshot = clock.snapshot(50 * MINUTE);
+    assert_eq!(snapshot.state, TimerState::Running);
+    assert_eq!(snapshot.started, Some(30 * MINUTE));
+    assert_eq!(snapshot.elapsed, 20 * 60);
+    assert_eq!((snapshot.cycle, snapshot.sessions), (1, 1));
+
+    // The second session earns the long break
+    let snapshot = clock.snapshot(56 * MINUTE);
+    assert_eq!(snapshot.state, TimerState::LongBreak);
+    assert_eq!((snapshot.elapsed, snapshot.length), (60, 15 * 60));
+    assert_eq!((snapshot.cycle, snapshot.sessions), (0, 2));
+    let completed = snapshot.completed.unwrap();
+    assert_eq!(
+      (completed.started, completed.length),
+      (30 * MINUTE, 25 * 60)
+    );
+    assert_eq!(
+      clock.boundaries,
+      vec![
+        Boundary::SessionFinished,
+        Boundary::BreakFinished,
+        Boundary::SessionFinished
+      ]
+    );
+
+    // Every segment it went through is kept in the history, ended the moment the next one began
+    let segments: Vec<(SegmentKind, u64, Option<u64>, bool)> = clock
+      .changes
+      .iter()
+      .filter(|segment| segment.ended.is_some())
+      .map(|segment| {
+        (
+          segment.kind,
+          segment.started,
+          segment.ended,
+          segment.completed,
+        )
+      })
+      .collect();
+    assert_eq!(
+      segments,
+      vec![
+        (SegmentKind::Work, 0, Some(25 * MINUTE), true),
+        (SegmentKind::Break, 25 * MINUTE, Some(30 * MINUTE), true),
+        (SegmentKind::Work, 30 * MINUTE, Some(55 * MINUTE), true),
+      ]
+    );
+  }
+
+  #[test]
+  fn completed_sessions_leave_out_their_pauses() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Pause, 10 * MINUTE);
+    clock.apply(TimerCommand::Start, 17 * MINUTE);
+    clock.apply(TimerCommand::Pause, 20 * MINUTE);
+    clock.apply(TimerCommand::Start, 21 * MINUTE);
+
+    // It runs its full length 33 minutes after it first started, having been paused for 8, and
+    // carries on for 7 more before the break
+    clock.apply(TimerCommand::Break, 40 * MINUTE);
+    let completed = clock.snapshot(40 * MINUTE).completed.unwrap();
+    assert_eq!(completed.started, 0);
+    assert_eq!((completed.focused, completed.overtime()), (32 * 60, 7 * 60));
+    let pauses: Vec<(u64, u64)> = completed
+      .pauses
+      .iter()
+      .map(|pause| (pause.started / MINUTE, pause.ended / MINUTE))
+      .collect();
+    assert_eq!(pauses, vec![(10, 17), (20, 21)]);
+  }
+
+  #[test]
+  fn splits_the_session_between_the_issues_it_tracked() {
+    let mut clock = Clock::new(settings(false));
+    let issue = |key: &str| TimerCommand::SetIssue(Some(key.to_string()));
+    clock.apply(issue("JRD-1"), 0);
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(issue("JRD-2"), 10 * MINUTE);
+    clock.apply(TimerCommand::Pause, 15 * MINUTE);
+    clock.apply(TimerCommand::Start, 17 * MINUTE);
+    clock.apply(issue("JRD-1"), 20 * MINUTE);
+    clock.apply(TimerCommand::Break, 30 * MINUTE);
+
+    // The issue picked last carries on into the break, ready for the next session
+    let snapshot = clock.snapshot(30 * MINUTE);
+    assert_eq!(snapshot.issue.as_deref(), Some("JRD-1"));
+    let completed = snapshot.completed.unwrap();
+    let issues: Vec<(Option<&str>, u64, u64)> = completed
+      .issues
+      .iter()
+      .map(|time| (time.issue_key.as_deref(), time.started, time.focused))
+      .collect();
+    assert_eq!(
+      issues,
+      vec![
+        (Some("JRD-1"), 0, 20 * 60),
+        (Some("JRD-2"), 10 * MINUTE, 8 * 60)
+      ]
+    );
+    assert_eq!(completed.focused, 28 * 60);
+  }
+
+  #[test]
+  fn reset_goes_back_to_a_fresh_session() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::SetLength(50 * 60), 0);
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Reset, 5_000);
+    let fresh = Clock::new(settings(false)).snapshot(5_000);
+    assert_eq!(clock.snapshot(5_000), fresh);
+
+    // The session that was reset is still in the history, cut short
+    let reset = clock.changes.last().unwrap();
+    assert_eq!((reset.ended, reset.completed), (Some(5_000), false));
+  }
+
+  #[test]
+  fn records_pauses_and_the_issue_in_the_history() {
+    let mut clock = Clock::new(settings(false));
+    clock.apply(TimerCommand::SetIssue(Some("JRD-1".to_string())), 0);
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Pause, MINUTE);
+    clock.apply(TimerCommand::Start, 3 * MINUTE);
+    clock.apply(TimerCommand::Pause, 4 * MINUTE);
+    clock.apply(
+      TimerCommand::SetIssue(Some("JRD-2".to_string())),
+      5 * MINUTE,
+    );
+    clock.apply(TimerCommand::Break, 6 * MINUTE);
+
+    let session = clock
+      .changes
+      .iter()
+      .rev()
+      .find(|segment| segment.kind == SegmentKind::Work)
+      .unwrap();
+    let pauses = vec![
+      Pause {
+        started: MINUTE,
+        ended: 3 * MINUTE,
+      },
+      Pause {
+        started: 4 * MINUTE,
+        ended: 6 * MINUTE,
+      },
+    ];
+    assert_eq!(session.pauses, pauses);
+    assert_eq!(session.issue_key.as_deref(), Some("JRD-2"));
+    let switch = IssueSwitch {
+      at: 5 * MINUTE,
+      from: Some("JRD-1".to_string()),
+    };
+    assert_eq!(session.switches, vec![switch]);
+    assert_eq!(
+      (session.ended, session.completed),
+      (Some(6 * MINUTE), false)
+    );
+
+    // Breaks aren't worked against an issue
+    let rest = clock.changes.last().unwrap();
+    assert_eq!(
+      (rest.kind, rest.ended, &rest.issue_key),
+      (SegmentKind::Break, None, &None)
+    );
+  }
+
+  #[test]
+  fn new_settings_apply_to_a_session_not_yet_started() {
+    let mut clock = Clock::new(settings(false));
+    let mut longer = settings(false);
+    longer.session_length = 50 * 60;
+    clock.apply(TimerCommand::Configure(longer.clone()), 0);
+    assert_eq!(clock.snapshot(0).length, 50 * 60);
+
+    clock.apply(TimerCommand::Start, 0);
+    clock.apply(TimerCommand::Configure(settings(false)), 1_000);
+    let snapshot = clock.snapshot(1_000);
+    assert_eq!(
+      (snapshot.length, snapshot.settings),
+      (50 * 60, settings(false))
+    );
+  }
+}
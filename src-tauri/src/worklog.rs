@@ -0,0 +1,91 @@
+//! A LongRunner process that delivers the worklogs in the outbox to Jira. It runs for as long as
+//! the app does, sleeping until the next entry is due or something new is added, and emits the
+//! outbox's counts along with every worklog it records. Delivered worklogs are noted against their
+//! session in the history.
+
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::history::History;
+use crate::jira::Session;
+use crate::longrunner::prelude::*;
+use crate::outbox::{now, Delivery, Outbox};
+use jiradoro_common::prelude::*;
+
+pub struct DeliverWorklogs {
+  pub outbox: Arc<Outbox>,
+  /// The Jira login worklogs are delivered with. Nothing is sent while logged out.
+  pub jira: Arc<RwLock<Option<Session>>>,
+  pub history: Arc<History>,
+}
+
+#[async_trait]
+impl LongRunnerProcess for DeliverWorklogs {
+  /// The number of worklogs delivered before the process was stopped
+  type Output = u64;
+  type Error = Infallible;
+
+  async fn start(self, tools: ProcessTools) -> Result<u64, Infallible> {
+    let mut delivered = 0;
+    let mut status = None;
+
+    loop {
+      let current = self.outbox.status();
+      if status.as_ref() != Some(&current) {
+        tools.emit(Payload::Outbox(current.clone())).await;
+        status = Some(current);
+      }
+
+      let client = {
+        let jira = self.jira.read().await;
+        jira.as_ref().map(|session| session.client.clone())
+      };
+
+      let now = now();
+      if let (Some(client), Some(entry)) = (&client, self.outbox.due(now)) {
+        match self.outbox.deliver(client, &entry, now).await {
+          Delivery::Delivered(worklog) => {
+            delivered += 1;
+            if let Err(err) = self.history.logged(&entry.segment, &worklog.id) {
+              error!(
+                "Could not note worklog {} in the history: {}",
+                worklog.id, err
+              );
+            }
+            tools.emit(Payload::Worklog(worklog)).await;
+          }
+          Delivery::Retrying { at, error } => {
+            let seconds = at.saturating_sub(now) / 1_000;
+            tools
+              .log(format!("Retrying {} in {}s: {}", entry.id, seconds, error))
+              .await
+          }
+          Delivery::Failed(error) => {
+            tools
+              .log(format!("Jira refused {}: {}", entry.id, error))
+              .await
+          }
+        }
+        continue;
+      }
+
+      // Sleep until the next entry is due, or until there is a reason to look again
+      let wait = match (&client, self.outbox.next_due()) {
+        (Some(_), Some(due)) => Some(Duration::from_millis(due.saturating_sub(now))),
+        _ => None,
+      };
+      tokio::select! {
+        _ = tools.cancellation_token.cancelled() => break,
+        _ = self.outbox.changed() => (),
+        _ = tokio::time::sleep(wait.unwrap_or_default()), if wait.is_some() => (),
+      }
+    }
+
+    info!("Delivered {} worklogs before stopping", delivered);
+    Ok(delivered)
+  }
+}